@@ -0,0 +1,444 @@
+use crate::state::ShellState;
+
+/// Evaluates a shell arithmetic expression as found in `$((...))` and
+/// `((...))`. Variables are read and assigned through `state`.
+pub fn evaluate(expression: &str, state: &mut ShellState) -> Result<i64, String> {
+    evaluate_nested(expression, state, 0)
+}
+
+// Variables holding expressions are evaluated recursively; this keeps
+// `a=b b=a` from looping forever.
+const MAX_RECURSION: usize = 64;
+
+fn evaluate_nested(expression: &str, state: &mut ShellState, depth: usize) -> Result<i64, String> {
+    if depth > MAX_RECURSION {
+        return Err(format!("{expression}: expression recursion level exceeded"));
+    }
+
+    let tokens = tokenize(expression).map_err(|error| format!("{expression}: {error}"))?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = ExprParser { tokens, pos: 0 };
+    let expr = parser
+        .parse_comma()
+        .map_err(|error| format!("{expression}: {error}"))?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(format!(
+            "{expression}: syntax error in expression (error token is \"{}\")",
+            token.text()
+        ));
+    }
+
+    let mut evaluator = Evaluator { state, depth };
+    evaluator
+        .eval(&expr)
+        .map_err(|error| format!("{expression}: {error}"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match self {
+            Token::Num(n) => n.to_string(),
+            Token::Ident(name) => name.clone(),
+            Token::Op(op) => op.to_string(),
+        }
+    }
+}
+
+// Longest operators first so that tokenizing is a greedy prefix match.
+const OPERATORS: [&str; 36] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^",
+    "|", "?", ":",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        if c.is_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_digit() {
+            let start = idx;
+            while idx < chars.len()
+                && (chars[idx].is_ascii_alphanumeric() || "#@_".contains(chars[idx]))
+            {
+                idx += 1;
+            }
+            let literal: String = chars[start..idx].iter().collect();
+            tokens.push(Token::Num(parse_number(&literal)?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_') {
+                idx += 1;
+            }
+            tokens.push(Token::Ident(chars[start..idx].iter().collect()));
+        } else if c == '(' || c == ')' || c == ',' {
+            tokens.push(Token::Op(match c {
+                '(' => "(",
+                ')' => ")",
+                _ => ",",
+            }));
+            idx += 1;
+        } else {
+            let rest: String = chars[idx..].iter().take(3).collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    idx += op.len();
+                }
+                None => {
+                    let rest: String = chars[idx..].iter().collect();
+                    return Err(format!(
+                        "syntax error: operand expected (error token is \"{rest}\")"
+                    ));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses decimal, octal (`017`), hexadecimal (`0x1f`) and `base#digits`
+/// literals, with bases from 2 to 64.
+fn parse_number(literal: &str) -> Result<i64, String> {
+    let invalid = || format!("{literal}: value too great for base (error token is \"{literal}\")");
+
+    let (base, digits) = if let Some((base, digits)) = literal.split_once('#') {
+        let base: u32 = base.parse().map_err(|_| invalid())?;
+        if !(2..=64).contains(&base) {
+            return Err(format!(
+                "{literal}: invalid arithmetic base (error token is \"{literal}\")"
+            ));
+        }
+        (base, digits)
+    } else if let Some(hex) = literal
+        .strip_prefix("0x")
+        .or_else(|| literal.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        (8, &literal[1..])
+    } else {
+        (10, literal)
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    PreIncrement(String, i64),
+    PostIncrement(String, i64),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(String, &'static str, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+/// Binding power of binary operators, loosest first.
+fn precedence(op: &str) -> Option<u8> {
+    let prec = match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    };
+    Some(prec)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn parse_comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_assignment()?;
+        while self.eat_op(",") {
+            let rhs = self.parse_assignment()?;
+            expr = Expr::Comma(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Ident(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+            && ASSIGNMENT_OPERATORS.contains(op)
+        {
+            let (name, op) = (name.clone(), *op);
+            self.pos += 2;
+            let value = self.parse_assignment()?;
+            return Ok(Expr::Assign(name, op, Box::new(value)));
+        }
+        self.parse_ternary()
+    }
+
+    fn parse_ternary(&mut self) -> Result<Expr, String> {
+        let condition = self.parse_binary(1)?;
+        if !self.eat_op("?") {
+            return Ok(condition);
+        }
+
+        let if_true = self.parse_assignment()?;
+        if !self.eat_op(":") {
+            return Err(self.syntax_error("`:' expected for conditional expression"));
+        }
+        let if_false = self.parse_assignment()?;
+        Ok(Expr::Ternary(
+            Box::new(condition),
+            Box::new(if_true),
+            Box::new(if_false),
+        ))
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let op = *op;
+            let Some(prec) = precedence(op).filter(|prec| *prec >= min_prec) else {
+                break;
+            };
+            self.pos += 1;
+            // `**` is right-associative, everything else left-associative.
+            let next_prec = if op == "**" { prec } else { prec + 1 };
+            let rhs = self.parse_binary(next_prec)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op @ ("++" | "--"))) => {
+                let delta = if *op == "++" { 1 } else { -1 };
+                if let Some(Token::Ident(name)) = self.tokens.get(self.pos + 1) {
+                    let name = name.clone();
+                    self.pos += 2;
+                    return Ok(Expr::PreIncrement(name, delta));
+                }
+                // `--5` is two unary minuses.
+                let sign = if delta == 1 { "+" } else { "-" };
+                self.pos += 1;
+                let operand = self.parse_unary()?;
+                let inner = Expr::Unary(sign, Box::new(operand));
+                Ok(Expr::Unary(sign, Box::new(inner)))
+            }
+            Some(Token::Op(op @ ("!" | "~" | "-" | "+"))) => {
+                let op = *op;
+                self.pos += 1;
+                let operand = self.parse_unary()?;
+                Ok(Expr::Unary(op, Box::new(operand)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Num(n)) => {
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Op("++")) => {
+                        self.pos += 1;
+                        Ok(Expr::PostIncrement(name, 1))
+                    }
+                    Some(Token::Op("--")) => {
+                        self.pos += 1;
+                        Ok(Expr::PostIncrement(name, -1))
+                    }
+                    _ => Ok(Expr::Var(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                if !self.eat_op(")") {
+                    return Err(self.syntax_error("missing `)'"));
+                }
+                Ok(expr)
+            }
+            _ => Err(self.syntax_error("syntax error: operand expected")),
+        }
+    }
+
+    fn eat_op(&mut self, expected: &str) -> bool {
+        if let Some(Token::Op(op)) = self.tokens.get(self.pos)
+            && *op == expected
+        {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn syntax_error(&self, message: &str) -> String {
+        let rest: Vec<String> = self.tokens[self.pos..].iter().map(Token::text).collect();
+        format!("{message} (error token is \"{}\")", rest.join(" "))
+    }
+}
+
+struct Evaluator<'a> {
+    state: &'a mut ShellState,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, String> {
+        match expr {
+            Expr::Num(n) => Ok(*n),
+            Expr::Var(name) => self.read_var(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::PreIncrement(name, delta) => {
+                let value = self.read_var(name)?.wrapping_add(*delta);
+                self.write_var(name, value)
+            }
+            Expr::PostIncrement(name, delta) => {
+                let value = self.read_var(name)?;
+                self.write_var(name, value.wrapping_add(*delta))?;
+                Ok(value)
+            }
+            Expr::Binary("&&", lhs, rhs) => {
+                Ok((self.eval(lhs)? != 0 && self.eval(rhs)? != 0) as i64)
+            }
+            Expr::Binary("||", lhs, rhs) => {
+                Ok((self.eval(lhs)? != 0 || self.eval(rhs)? != 0) as i64)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                apply_binary(op, lhs, rhs)
+            }
+            Expr::Ternary(condition, if_true, if_false) => {
+                if self.eval(condition)? != 0 {
+                    self.eval(if_true)
+                } else {
+                    self.eval(if_false)
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let value = self.eval(value)?;
+                let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    Some(op) => apply_binary(op, self.read_var(name)?, value)?,
+                    None => value,
+                };
+                self.write_var(name, value)
+            }
+            Expr::Comma(lhs, rhs) => {
+                self.eval(lhs)?;
+                self.eval(rhs)
+            }
+        }
+    }
+
+    /// Unset and empty variables count as 0; any other value is itself
+    /// evaluated as an expression.
+    fn read_var(&mut self, name: &str) -> Result<i64, String> {
+        let value = self.state.get_var(name).unwrap_or("").trim().to_string();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse::<i64>() {
+            return Ok(n);
+        }
+        evaluate_nested(&value, self.state, self.depth + 1)
+    }
+
+    fn write_var(&mut self, name: &str, value: i64) -> Result<i64, String> {
+        self.state.set_var(name, &value.to_string());
+        Ok(value)
+    }
+}
+
+fn apply_binary(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    let value = match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => {
+            return Err("division by 0 (error token is \"0\")".to_string());
+        }
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "**" if rhs < 0 => {
+            return Err("exponent less than 0".to_string());
+        }
+        "**" => lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32),
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "<" => (lhs < rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "&" => lhs & rhs,
+        "^" => lhs ^ rhs,
+        "|" => lhs | rhs,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        _ => return Err(format!("unknown operator `{op}'")),
+    };
+    Ok(value)
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::commands;
+use crate::error::{self, ShellError};
+use crate::state::ShellState;
+
+/// A command run by the shell itself rather than as a program. Builtins
+/// are looked up by name in the shell's [`Registry`], to which embedders
+/// can add their own.
+pub trait Builtin: Send + Sync {
+    /// The name the builtin is invoked by.
+    fn name(&self) -> &str;
+
+    /// The text shown by `help`, whose first line is a synopsis such as
+    /// `echo [-neE] [arg ...]`.
+    fn help(&self) -> &str;
+
+    /// Runs the builtin with its arguments, not including its name, and
+    /// returns its exit status. `env` holds the assignments preceding the
+    /// command, which are also set in `state` while it runs.
+    fn run(
+        &self,
+        args: &[String],
+        env: &[(String, String)],
+        io: &mut BuiltinIo,
+        state: &mut ShellState,
+    ) -> i32;
+}
+
+/// The output streams of a builtin. Builtins write through these handles
+/// rather than printing, so that their output can go to a pipe, a file or
+/// a buffer as well as to the shell's own standard output and error.
+pub struct BuiltinIo<'a> {
+    pub stdout: Box<dyn Write + 'a>,
+    pub stderr: Box<dyn Write + 'a>,
+}
+
+impl BuiltinIo<'static> {
+    /// The shell's standard output and error, wherever redirects point
+    /// them.
+    pub fn standard() -> Self {
+        BuiltinIo {
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+        }
+    }
+}
+
+impl BuiltinIo<'_> {
+    /// Writes the output of a builtin, reporting failures such as a closed
+    /// pipe as bash does.
+    pub fn write_output(&mut self, cmd: &str, output: &[u8]) -> i32 {
+        match self
+            .stdout
+            .write_all(output)
+            .and_then(|_| self.stdout.flush())
+        {
+            Ok(()) => 0,
+            Err(error) => {
+                let message = format!("write error: {}", error::describe(&error));
+                ShellError::builtin(cmd, message).report(&mut self.stderr)
+            }
+        }
+    }
+}
+
+/// The builtins of a shell by name. A new registry holds the standard
+/// ones; registering a builtin under a taken name replaces the previous.
+#[derive(Clone)]
+pub struct Registry {
+    builtins: BTreeMap<String, Arc<dyn Builtin>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let mut registry = Registry::empty();
+        for builtin in commands::standard_builtins() {
+            registry.register(builtin);
+        }
+        registry
+    }
+
+    /// A registry without any builtin, not even `exit`.
+    pub fn empty() -> Self {
+        Registry {
+            builtins: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `builtin`, returning the one it replaces, if any.
+    pub fn register(&mut self, builtin: impl Builtin + 'static) -> Option<Arc<dyn Builtin>> {
+        let name = builtin.name().to_string();
+        self.builtins.insert(name, Arc::new(builtin))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Arc<dyn Builtin>> {
+        self.builtins.remove(name)
+    }
+
+    /// The builtin called `name`, shared so that it can run with the state
+    /// holding this registry.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Builtin>> {
+        self.builtins.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builtins.contains_key(name)
+    }
+
+    /// The builtins, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Builtin> {
+        self.builtins.values().map(|builtin| builtin.as_ref())
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.builtins.keys()).finish()
+    }
+}
@@ -1,62 +1,927 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::builtins::{Builtin, BuiltinIo};
+use crate::conditional;
 use crate::enums::WriteFileMode;
+use crate::error::{self, ShellError};
+use crate::expand;
 use crate::interpret;
+use crate::jobs;
 use crate::parse;
+use crate::printf;
+use crate::state::{FlowControl, ShellState};
 use crate::utils;
 
-pub fn do_type(args: &[&str]) {
-    let supported = ["echo", "exit", "type"];
-    let arg = args.get(0).unwrap();
+/// A builtin of the shell itself, run by one of the functions below.
+struct Standard {
+    name: &'static str,
+    help: &'static str,
+    run: fn(&str, &[String], &mut ShellState, &mut BuiltinIo) -> i32,
+}
+
+impl Builtin for Standard {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn run(
+        &self,
+        args: &[String],
+        _env: &[(String, String)],
+        io: &mut BuiltinIo,
+        state: &mut ShellState,
+    ) -> i32 {
+        (self.run)(self.name, args, state, io)
+    }
+}
+
+const STANDARD_BUILTINS: [Standard; 23] = [
+    Standard {
+        name: "break",
+        help: "break [n]\n    Exit from the Nth enclosing for, while or until loop.",
+        run: |cmd, args, state, io| do_loop_control(cmd, args, state, FlowControl::Break, io),
+    },
+    Standard {
+        name: "continue",
+        help: "continue [n]\n    Resume the next iteration of the Nth enclosing loop.",
+        run: |cmd, args, state, io| do_loop_control(cmd, args, state, FlowControl::Continue, io),
+    },
+    Standard {
+        name: "return",
+        help: "return [n]\n    Return from a function or sourced script with status N.",
+        run: |_, args, state, io| do_return(args, state, io),
+    },
+    Standard {
+        name: "local",
+        help: "local name[=value] ...\n    Declare variables visible until the function returns.",
+        run: |_, args, state, io| do_local(args, state, io),
+    },
+    Standard {
+        name: "exit",
+        help: "exit [n]\n    Exit the shell with status N.",
+        run: |_, args, state, io| do_exit(args, state, io),
+    },
+    Standard {
+        name: "export",
+        help: "export [name[=value] ...]\n    Pass variables to the programs the shell runs.",
+        run: |_, args, state, io| do_export(args, state, io),
+    },
+    Standard {
+        name: "unset",
+        help: "unset [-f] [-v] name ...\n    Remove variables, or functions with -f.",
+        run: |_, args, state, _| do_unset(args, state),
+    },
+    Standard {
+        name: "source",
+        help: "source filename [arguments]\n    Run the commands of a file in the current shell.",
+        run: |cmd, args, state, io| do_source(cmd, args, state, io),
+    },
+    Standard {
+        name: ".",
+        help: ". filename [arguments]\n    Run the commands of a file in the current shell.",
+        run: |cmd, args, state, io| do_source(cmd, args, state, io),
+    },
+    Standard {
+        name: "eval",
+        help: "eval [arg ...]\n    Run the arguments, joined by spaces, as commands.",
+        run: |_, args, state, _| do_eval(args, state),
+    },
+    Standard {
+        name: "exec",
+        help: "exec [command [arguments]]\n    Replace the shell with a command.",
+        run: |_, args, state, _| do_exec(args, state),
+    },
+    Standard {
+        name: "read",
+        help: "read [-rs] [-a array] [-d delim] [-n nchars] [-p prompt] [-t timeout] [name ...]\n    Read a line from standard input and split it into variables.",
+        run: |_, args, state, io| do_read(args, state, io),
+    },
+    Standard {
+        name: "echo",
+        help: "echo [-neE] [arg ...]\n    Write the arguments to standard output.",
+        run: |_, args, _, io| do_echo(args, io),
+    },
+    Standard {
+        name: "printf",
+        help: "printf [-v var] format [arguments]\n    Write the arguments formatted as format says.",
+        run: |_, args, state, io| do_printf(args, state, io),
+    },
+    Standard {
+        name: "test",
+        help: "test [expr]\n    Evaluate a conditional expression.",
+        run: |cmd, args, _, io| do_test(cmd, args, io),
+    },
+    Standard {
+        name: "[",
+        help: "[ arg ... ]\n    Evaluate a conditional expression, as test does.",
+        run: |cmd, args, _, io| do_test(cmd, args, io),
+    },
+    Standard {
+        name: "type",
+        help: "type name ...\n    Tell how each name would be run as a command.",
+        run: |_, args, state, io| do_type(args, state, io),
+    },
+    Standard {
+        name: "history",
+        help: "history [n] | history -r|-w|-a [filename]\n    Show, read or write the command history.",
+        run: |_, args, state, io| do_history(args, state, io),
+    },
+    Standard {
+        name: "help",
+        help: "help [pattern ...]\n    Describe the builtins matching the patterns, or list them all.",
+        run: |_, args, state, io| do_help(args, state, io),
+    },
+    Standard {
+        name: "cd",
+        help: "cd [dir]\n    Change the working directory to DIR, by default HOME, or OLDPWD with -.",
+        run: |_, args, state, io| do_cd(args, state, io),
+    },
+    Standard {
+        name: "jobs",
+        help: "jobs [-p]\n    List the background jobs, or only their process ids with -p.",
+        run: |_, args, state, io| do_jobs(args, state, io),
+    },
+    Standard {
+        name: "wait",
+        help: "wait [id ...]\n    Wait for the given jobs or processes, or for all background jobs.",
+        run: |_, args, state, io| do_wait(args, state, io),
+    },
+    Standard {
+        name: ":",
+        help: ": [arguments]\n    Do nothing, successfully.",
+        run: |_, _, _, _| 0,
+    },
+];
 
-    if supported.contains(arg) {
-        eprintln!("{arg} is a shell builtin")
+/// The builtins every shell starts with.
+pub(crate) fn standard_builtins() -> impl Iterator<Item = impl Builtin> {
+    STANDARD_BUILTINS.into_iter()
+}
+
+/// Runs the builtin called `cmd` from the shell's registry, with its output
+/// going to `io`, returning `None` when there is no such builtin. The
+/// commands run by `eval`, `source` and `exec` write to the shell's
+/// standard streams.
+pub fn run_builtin(
+    cmd: &str,
+    args: &[String],
+    env: &[(String, String)],
+    state: &mut ShellState,
+    io: &mut BuiltinIo,
+) -> Option<i32> {
+    let builtin = state.builtins.get(cmd)?;
+    Some(builtin.run(args, env, io, state))
+}
+
+/// `cd [dir]`: changes the working directory to `dir`, by default `HOME`,
+/// or to `OLDPWD` with `-`. `..` removes the last component of the shell's
+/// working directory, as symbolic links were followed to reach it, unless
+/// the resulting directory does not exist.
+fn do_cd(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let (target, print) = match args.first().map(String::as_str) {
+        None => (state.get_var("HOME"), false),
+        Some("-") => (state.get_var("OLDPWD"), true),
+        Some(dir) => (Some(dir), false),
+    };
+    let Some(target) = target.map(String::from) else {
+        let name = if print { "OLDPWD" } else { "HOME" };
+        return ShellError::builtin("cd", format!("{name} not set")).report(&mut io.stderr);
+    };
+
+    let logical = logical_path(&state.cwd, &target);
+    let cwd = if env::set_current_dir(&logical).is_ok() {
+        logical
     } else {
-        if let Some(found) = utils::find_executable_on_path(arg) {
-            let full_path = found.to_str().unwrap();
-            eprintln!("{arg} is {full_path}");
+        let physical = state.cwd.join(&target);
+        match env::set_current_dir(physical).and_then(|_| env::current_dir()) {
+            Ok(cwd) => cwd,
+            Err(error) => {
+                let message = format!("{target}: {}", error::describe(&error));
+                return ShellError::builtin("cd", message).report(&mut io.stderr);
+            }
+        }
+    };
+
+    let previous = std::mem::replace(&mut state.cwd, cwd);
+    state.set_var("OLDPWD", &previous.to_string_lossy());
+    let pwd = state.cwd.to_string_lossy().into_owned();
+    state.set_var("PWD", &pwd);
+    if print {
+        let output = format!("{}\n", state.cwd.display());
+        return io.write_output("cd", output.as_bytes());
+    }
+    0
+}
+
+/// Resolves `dir` against `cwd` without following symbolic links.
+fn logical_path(cwd: &Path, dir: &str) -> PathBuf {
+    let mut path = cwd.to_path_buf();
+    for component in Path::new(dir).components() {
+        match component {
+            Component::RootDir => path = PathBuf::from("/"),
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(name) => path.push(name),
+            Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    path
+}
+
+/// `help [pattern...]`: shows the help of the builtins whose names match
+/// the patterns, or the synopsis of every builtin.
+fn do_help(args: &[String], state: &ShellState, io: &mut BuiltinIo) -> i32 {
+    let mut output = String::new();
+    if args.is_empty() {
+        for builtin in state.builtins.iter() {
+            let synopsis = builtin.help().lines().next().unwrap_or_default();
+            output.push_str(&format!("{synopsis}\n"));
+        }
+        return io.write_output("help", output.as_bytes());
+    }
+
+    for pattern in args {
+        let matching: Vec<&dyn Builtin> = state
+            .builtins
+            .iter()
+            .filter(|builtin| utils::pattern_matches(pattern, builtin.name()))
+            .collect();
+        if matching.is_empty() {
+            let message = format!("no help topics match `{pattern}'");
+            return ShellError::builtin("help", message).report(&mut io.stderr);
+        }
+        for builtin in matching {
+            output.push_str(&format!("{}: {}\n", builtin.name(), builtin.help()));
+        }
+    }
+    io.write_output("help", output.as_bytes())
+}
+
+/// `type name...`: tells whether each name is a function, a builtin or a
+/// program found in `PATH`.
+fn do_type(args: &[String], state: &ShellState, io: &mut BuiltinIo) -> i32 {
+    let mut status = 0;
+    for name in args {
+        if state.functions.contains_key(name) {
+            let _ = writeln!(io.stdout, "{name} is a function");
+        } else if state.builtins.contains(name) {
+            let _ = writeln!(io.stdout, "{name} is a shell builtin");
+        } else if let Some(path) = interpret::resolve_program(name, state) {
+            let _ = writeln!(io.stdout, "{name} is {}", path.display());
         } else {
-            eprintln!("{arg}: not found");
+            status =
+                ShellError::builtin("type", format!("{name}: not found")).report(&mut io.stderr);
         }
     }
+    status
 }
 
-pub fn do_history(args: &[&str], history: &mut Vec<String>) {
-    if let Some(arg) = args.get(0) {
-        let pathref = args.get(1).unwrap();
-        match *arg {
-            "-r" => {
-                utils::fill_history(pathref, history);
+/// `history [N]` lists the last N entries of the history, by default all
+/// of them. `history -r|-w|-a [file]` reads the history from `file`,
+/// writes it there, or appends the new entries, `file` defaulting to
+/// `HISTFILE`.
+fn do_history(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let count = match args.first().map(String::as_str) {
+        Some(flag @ ("-r" | "-w" | "-a")) => {
+            let path = args
+                .get(1)
+                .map(String::as_str)
+                .or(state.get_var("HISTFILE"));
+            let Some(path) = path.map(String::from) else {
+                let message = format!("{flag}: no history file");
+                return ShellError::builtin("history", message).report(&mut io.stderr);
+            };
+            let result = match flag {
+                "-r" => utils::fill_history(&path, &mut state.history),
+                "-w" => utils::dump_history(&path, &mut state.history, WriteFileMode::OverWrite),
+                _ => utils::dump_history(&path, &mut state.history, WriteFileMode::Append),
+            };
+            return match result {
+                Ok(()) => 0,
+                Err(error) => {
+                    ShellError::builtin("history", error.to_string()).report(&mut io.stderr)
+                }
+            };
+        }
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                let message = format!("{arg}: numeric argument required");
+                return ShellError::builtin("history", message).report(&mut io.stderr);
             }
-            "-w" | "-a" => {
-                let mode = if *arg == "-a" {
-                    WriteFileMode::Append
-                } else {
-                    WriteFileMode::Append
+        },
+        None => state.history.len(),
+    };
+
+    let skipped = state.history.len().saturating_sub(count);
+    let mut output = String::new();
+    for (idx, line) in state.history.iter().enumerate().skip(skipped) {
+        output.push_str(&format!("{}  {line}\n", idx + 1));
+    }
+    io.write_output("history", output.as_bytes())
+}
+
+/// `break [N]` and `continue [N]`, acting on the Nth enclosing loop.
+fn do_loop_control(
+    cmd: &str,
+    args: &[String],
+    state: &mut ShellState,
+    control: fn(usize) -> FlowControl,
+    io: &mut BuiltinIo,
+) -> i32 {
+    if state.loop_depth == 0 {
+        let message = "only meaningful in a `for', `while', or `until' loop";
+        return ShellError::builtin(cmd, message)
+            .with_status(0)
+            .report(&mut io.stderr);
+    }
+
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(count) if count >= 1 => count as usize,
+            Ok(_) => {
+                let message = format!("{arg}: loop count out of range");
+                return ShellError::builtin(cmd, message).report(&mut io.stderr);
+            }
+            Err(_) => {
+                let message = format!("{arg}: numeric argument required");
+                return ShellError::builtin(cmd, message).report(&mut io.stderr);
+            }
+        },
+    };
+    state.flow_control = Some(control(count.min(state.loop_depth)));
+    0
+}
+
+/// `return [N]`, leaving the running function with status N, by default
+/// the status of the last command.
+fn do_return(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    if !state.in_function() && state.source_depth == 0 {
+        let message = "can only `return' from a function or sourced script";
+        return ShellError::builtin("return", message).report(&mut io.stderr);
+    }
+
+    let status = match args.first() {
+        None => state.last_status,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(status) => (status & 0xff) as i32,
+            Err(_) => ShellError::builtin("return", format!("{arg}: numeric argument required"))
+                .with_status(2)
+                .report(&mut io.stderr),
+        },
+    };
+    state.flow_control = Some(FlowControl::Return(status));
+    status
+}
+
+/// `exit [N]`, leaving the shell (or the subshell) with status N, by
+/// default the status of the last command.
+fn do_exit(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let status = match args.first() {
+        None => state.last_status,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(status) => (status & 0xff) as i32,
+            Err(_) => ShellError::builtin("exit", format!("{arg}: numeric argument required"))
+                .with_status(2)
+                .report(&mut io.stderr),
+        },
+    };
+    state.flow_control = Some(FlowControl::Exit(status));
+    status
+}
+
+/// `export name[=value]...`, handing variables to the programs we run.
+/// Without arguments, lists the exported variables.
+fn do_export(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    if args.is_empty() {
+        let mut vars = state.exported_vars();
+        vars.sort();
+        for (name, value) in vars {
+            let _ = writeln!(io.stdout, "declare -x {name}=\"{value}\"");
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !expand::is_valid_name(name) {
+            let message = format!("`{arg}': not a valid identifier");
+            status = ShellError::builtin("export", message).report(&mut io.stderr);
+            continue;
+        }
+        match value {
+            Some(value) => state.set_var(name, value),
+            None if state.get_var(name).is_none() => state.set_var(name, ""),
+            None => {}
+        }
+        state.export_var(name);
+    }
+    status
+}
+
+/// `unset [-f|-v] name...`, removing variables, or functions with `-f`.
+fn do_unset(args: &[String], state: &mut ShellState) -> i32 {
+    let functions = args.first().is_some_and(|arg| arg == "-f");
+    let names = args.iter().skip_while(|arg| *arg == "-f" || *arg == "-v");
+
+    for name in names {
+        if functions {
+            state.functions.remove(name);
+        } else {
+            state.unset_var(name);
+        }
+    }
+    0
+}
+
+/// `source file [args]` and `. file [args]`: runs the commands of a file
+/// in the current shell, with `args` as positional parameters meanwhile.
+/// Names without a slash are searched for in `PATH`, then in the current
+/// directory.
+fn do_source(cmd: &str, args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let Some((name, args)) = args.split_first() else {
+        return ShellError::builtin(cmd, "filename argument required")
+            .with_status(2)
+            .report(&mut io.stderr);
+    };
+    let found = if name.contains('/') {
+        None
+    } else {
+        utils::find_file_in(name, state.get_var("PATH").unwrap_or(""))
+    };
+    let path = found.unwrap_or_else(|| PathBuf::from(name));
+
+    let positional = if args.is_empty() {
+        None
+    } else {
+        Some(std::mem::replace(&mut state.positional, args.to_vec()))
+    };
+    let loop_depth = std::mem::take(&mut state.loop_depth);
+    state.source_depth += 1;
+
+    let mut status = match interpret::run_file(&path, state) {
+        Ok(status) => status,
+        Err(error) => {
+            let message = format!("{name}: {}", error::describe(&error));
+            ShellError::builtin(cmd, message).report(&mut io.stderr)
+        }
+    };
+    if let Some(FlowControl::Return(returned)) = state.flow_control {
+        state.flow_control = None;
+        status = returned;
+    }
+
+    state.source_depth -= 1;
+    state.loop_depth = loop_depth;
+    if let Some(positional) = positional {
+        state.positional = positional;
+    }
+    status
+}
+
+/// `eval args...`: parses the arguments, joined by spaces, as commands
+/// and runs them.
+fn do_eval(args: &[String], state: &mut ShellState) -> i32 {
+    match parse::run_parser(&args.join(" ")) {
+        Ok(commands) => interpret::interpret_command(commands, state),
+        Err(error) => interpret::report(error.into()),
+    }
+}
+
+/// `jobs [-p]`: lists the background jobs, or only their process ids with
+/// `-p`. Finished jobs are listed once, then forgotten.
+fn do_jobs(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let pids_only = match args {
+        [] => false,
+        [flag] if flag == "-p" => true,
+        [arg, ..] => {
+            return ShellError::builtin("jobs", format!("{arg}: invalid option"))
+                .with_status(2)
+                .report(&mut io.stderr);
+        }
+    };
+    let output = jobs::list(state, pids_only);
+    io.write_output("jobs", output.as_bytes())
+}
+
+/// `wait [id...]`: waits for the jobs given as `%n` or process ids and
+/// returns the status of the last one, or waits for all background jobs.
+fn do_wait(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    if args.is_empty() {
+        jobs::wait_all(state);
+        return 0;
+    }
+
+    let mut status = 0;
+    for spec in args {
+        status = match jobs::find(state, spec) {
+            Some(idx) => jobs::wait(state, idx),
+            None => {
+                let message = match spec.strip_prefix('%') {
+                    Some(_) => format!("{spec}: no such job"),
+                    None => format!("pid {spec} is not a child of this shell"),
                 };
-                utils::dump_history(pathref, history, mode);
+                ShellError::builtin("wait", message)
+                    .with_status(127)
+                    .report(&mut io.stderr)
+            }
+        };
+    }
+    status
+}
+
+/// `exec cmd args...`: replaces the shell with `cmd`, and failing that,
+/// leaves a non-interactive shell. Without a command, `exec` only applies
+/// its redirects, which is handled by the interpreter.
+fn do_exec(args: &[String], state: &mut ShellState) -> i32 {
+    let Some((cmd, args)) = args.split_first() else {
+        return 0;
+    };
+    let status = interpret::exec_program(cmd, args, state);
+    if !state.options.interactive {
+        state.flow_control = Some(FlowControl::Exit(status));
+    }
+    status
+}
+
+/// `echo [-neE] [arg...]`: prints the arguments separated by spaces. `-n`
+/// leaves out the final newline, and `-e` expands backslash escapes, a
+/// `\c` among which ends the output there.
+fn do_echo(args: &[String], io: &mut BuiltinIo) -> i32 {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut words = args;
+
+    // Only arguments made of known option letters are options.
+    while let Some(flags) = words.first().and_then(|arg| arg.strip_prefix('-'))
+        && !flags.is_empty()
+        && flags.chars().all(|flag| "neE".contains(flag))
+    {
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
             }
-            _ => {
-                eprintln!(
-                    "history supports only the `-r` argument, but you passed '{}' in",
-                    arg
-                );
+        }
+        words = &words[1..];
+    }
+
+    let mut output = vec![];
+    for (idx, word) in words.iter().enumerate() {
+        if idx > 0 {
+            output.push(b' ');
+        }
+        if !escapes {
+            output.extend_from_slice(word.as_bytes());
+            continue;
+        }
+        let (bytes, stop) = printf::expand_escapes(word, true);
+        output.extend(bytes);
+        if stop {
+            return io.write_output("echo", &output);
+        }
+    }
+    if newline {
+        output.push(b'\n');
+    }
+    io.write_output("echo", &output)
+}
+
+/// `printf [-v var] format [arg...]`: formats the arguments, into the
+/// variable `var` if given.
+fn do_printf(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let (variable, args) = match args {
+        [flag, name, rest @ ..] if flag == "-v" => (Some(name), rest),
+        [flag] if flag == "-v" => {
+            return ShellError::builtin("printf", "-v: option requires an argument")
+                .with_status(2)
+                .report(&mut io.stderr);
+        }
+        [flag, rest @ ..] if flag == "--" => (None, rest),
+        _ => (None, args),
+    };
+    let Some((format, args)) = args.split_first() else {
+        return ShellError::builtin("printf", "usage: printf [-v var] format [arguments]")
+            .with_status(2)
+            .report(&mut io.stderr);
+    };
+    if let Some(name) = variable
+        && !expand::is_valid_name(name)
+    {
+        return ShellError::builtin("printf", format!("`{name}': not a valid identifier"))
+            .with_status(2)
+            .report(&mut io.stderr);
+    }
+
+    let formatted = printf::format(format, args);
+    for error in &formatted.errors {
+        ShellError::builtin("printf", error).report(&mut io.stderr);
+    }
+    let status = match variable {
+        Some(name) => {
+            state.set_var(name, &String::from_utf8_lossy(&formatted.output));
+            0
+        }
+        None => io.write_output("printf", &formatted.output),
+    };
+    if formatted.errors.is_empty() {
+        status
+    } else {
+        1
+    }
+}
+
+/// `test expression` and `[ expression ]`.
+fn do_test(cmd: &str, args: &[String], io: &mut BuiltinIo) -> i32 {
+    let args = match (cmd, args.split_last()) {
+        ("[", Some((last, args))) if last == "]" => args,
+        ("[", _) => {
+            return ShellError::builtin("[", "missing `]'")
+                .with_status(2)
+                .report(&mut io.stderr);
+        }
+        _ => args,
+    };
+    match conditional::test(args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(error) => ShellError::builtin(cmd, error)
+            .with_status(2)
+            .report(&mut io.stderr),
+    }
+}
+
+/// The options of `read`.
+struct ReadOptions {
+    raw: bool,
+    silent: bool,
+    prompt: Option<String>,
+    timeout: Option<f64>,
+    count: Option<usize>,
+    delimiter: u8,
+    array: Option<String>,
+}
+
+/// Parses `read` options, which may be grouped as in `-rp prompt`, and
+/// returns them with the remaining names.
+fn parse_read_options(args: &[String]) -> Result<(ReadOptions, &[String]), String> {
+    let mut options = ReadOptions {
+        raw: false,
+        silent: false,
+        prompt: None,
+        timeout: None,
+        count: None,
+        delimiter: b'\n',
+        array: None,
+    };
+    let mut idx = 0;
+
+    while let Some(arg) = args.get(idx) {
+        idx += 1;
+        if arg == "--" {
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            idx -= 1;
+            break;
+        };
 
-                return;
+        for (pos, flag) in flags.char_indices() {
+            match flag {
+                'r' => options.raw = true,
+                's' => options.silent = true,
+                'p' | 't' | 'n' | 'd' | 'a' => {
+                    // The value is the rest of this argument, or the next one.
+                    let rest = &flags[pos + 1..];
+                    let value = if rest.is_empty() {
+                        idx += 1;
+                        args.get(idx - 1)
+                            .cloned()
+                            .ok_or(format!("-{flag}: option requires an argument"))?
+                    } else {
+                        rest.to_string()
+                    };
+                    match flag {
+                        'p' => options.prompt = Some(value),
+                        't' => match value.parse::<f64>() {
+                            Ok(timeout) if timeout >= 0.0 => options.timeout = Some(timeout),
+                            _ => return Err(format!("{value}: invalid timeout specification")),
+                        },
+                        'n' => match value.parse::<usize>() {
+                            Ok(count) => options.count = Some(count),
+                            Err(_) => return Err(format!("{value}: invalid number")),
+                        },
+                        'd' => options.delimiter = value.bytes().next().unwrap_or(0),
+                        _ => options.array = Some(value),
+                    }
+                    break;
+                }
+                _ => return Err(format!("-{flag}: invalid option")),
             }
         }
+    }
+    Ok((options, &args[idx..]))
+}
+
+/// `read [-rs] [-p prompt] [-t timeout] [-n count] [-d delim] [-a array]
+/// [name...]`: reads a line from standard input and splits it on IFS into
+/// the names, the last one getting the rest of the line. Input is read a
+/// byte at a time so that nothing past the line is consumed.
+fn do_read(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    let (options, names) = match parse_read_options(args) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            return ShellError::builtin("read", error)
+                .with_status(2)
+                .report(&mut io.stderr);
+        }
+    };
+    if let Some(name) = names
+        .iter()
+        .chain(options.array.iter())
+        .find(|name| !expand::is_valid_name(name))
+    {
+        let message = format!("`{name}': not a valid identifier");
+        return ShellError::builtin("read", message).report(&mut io.stderr);
+    }
+
+    let fd = libc::STDIN_FILENO;
+    if options.timeout == Some(0.0) {
+        // `-t 0` only tells whether there is input to read.
+        return match utils::wait_readable(fd, Duration::ZERO) {
+            Ok(true) => 0,
+            _ => 1,
+        };
+    }
+    if let Some(prompt) = &options.prompt
+        && utils::is_terminal(fd)
+    {
+        let _ = write!(io.stderr, "{prompt}");
+        let _ = io.stderr.flush();
+    }
+
+    let terminal = if options.silent {
+        utils::disable_echo(fd)
+    } else {
+        None
+    };
+    let (input, status) = read_input(fd, &options, io);
+    if let Some(settings) = terminal {
+        utils::restore_terminal(fd, &settings);
+    }
+
+    let ifs = state
+        .get_var("IFS")
+        .unwrap_or(expand::DEFAULT_IFS)
+        .to_string();
+    if let Some(array) = &options.array {
+        state.set_array(array, split_read_input(&input, &ifs, usize::MAX));
+    } else if names.is_empty() {
+        let line: Vec<u8> = input.iter().map(|(byte, _)| *byte).collect();
+        state.set_var("REPLY", &String::from_utf8_lossy(&line));
     } else {
-        for (idx, line) in history.iter().enumerate() {
-            println!("{}  {}", idx + 1, line);
+        let fields = split_read_input(&input, &ifs, names.len());
+        for (idx, name) in names.iter().enumerate() {
+            state.set_var(name, fields.get(idx).map_or("", String::as_str));
         }
     }
+    status
 }
 
-pub fn handle_command(cmd: &str, args: &[&str], input_str: &str, history: &mut Vec<String>) {
-    match cmd.to_lowercase().as_str() {
-        "type" => do_type(&args),
-        "history" => do_history(&args, history),
-        _ => {
-            let commands = parse::run_parser(input_str);
-            interpret::interpret_command(commands);
+/// Reads up to the delimiter, returning each byte with whether it was
+/// escaped by a backslash, and the status of `read`: 1 at end of input,
+/// and 142 (as for SIGALRM) on timeout.
+fn read_input(fd: i32, options: &ReadOptions, io: &mut BuiltinIo) -> (Vec<(u8, bool)>, i32) {
+    let deadline = options
+        .timeout
+        .map(|timeout| Instant::now() + Duration::from_secs_f64(timeout));
+    let mut input = vec![];
+    let mut escaped = false;
+    let mut count = 0;
+    // Continuation bytes still expected to complete a UTF-8 character.
+    let mut continuation = 0;
+
+    if options.count == Some(0) {
+        return (input, 0);
+    }
+    loop {
+        let byte = match utils::read_byte(fd, deadline) {
+            Ok(Some(byte)) => byte,
+            Ok(None) => return (input, 1),
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => return (input, 142),
+            Err(error) => return (input, ShellError::io("read", error).report(&mut io.stderr)),
+        };
+
+        let was_escaped = escaped;
+        if escaped {
+            escaped = false;
+            // A backslash-newline continues the line.
+            if byte == b'\n' {
+                continue;
+            }
+        } else if !options.raw && byte == b'\\' {
+            escaped = true;
+            continue;
+        } else if byte == options.delimiter {
+            return (input, 0);
+        }
+
+        input.push((byte, was_escaped));
+        if byte & 0xc0 == 0x80 {
+            continuation = usize::saturating_sub(continuation, 1);
+        } else {
+            count += 1;
+            continuation = (byte.leading_ones() as usize).saturating_sub(1);
+        }
+        if options.count == Some(count) && continuation == 0 {
+            return (input, 0);
+        }
+    }
+}
+
+/// Splits the input of `read` into at most `max` fields on IFS. Escaped
+/// bytes never delimit fields, and the last field takes the rest of the
+/// input, without trailing IFS whitespace.
+fn split_read_input(input: &[(u8, bool)], ifs: &str, max: usize) -> Vec<String> {
+    let is_ifs = |&(byte, escaped): &(u8, bool)| !escaped && ifs.as_bytes().contains(&byte);
+    let is_whitespace = |entry: &(u8, bool)| is_ifs(entry) && b" \t\n".contains(&entry.0);
+    let text = |entries: &[(u8, bool)]| {
+        let bytes: Vec<u8> = entries.iter().map(|(byte, _)| *byte).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let mut fields = vec![];
+    let mut pos = input
+        .iter()
+        .take_while(|entry| is_whitespace(entry))
+        .count();
+    while pos < input.len() {
+        if fields.len() + 1 == max {
+            let mut end = input.len();
+            while end > pos && is_whitespace(&input[end - 1]) {
+                end -= 1;
+            }
+            fields.push(text(&input[pos..end]));
+            break;
+        }
+
+        let start = pos;
+        while pos < input.len() && !is_ifs(&input[pos]) {
+            pos += 1;
+        }
+        fields.push(text(&input[start..pos]));
+
+        // Whitespace around at most one other IFS character separates fields.
+        while pos < input.len() && is_whitespace(&input[pos]) {
+            pos += 1;
+        }
+        if pos < input.len() && is_ifs(&input[pos]) {
+            pos += 1;
+            while pos < input.len() && is_whitespace(&input[pos]) {
+                pos += 1;
+            }
+        }
+    }
+    fields
+}
+
+/// `local name[=value]...`, declaring variables that only exist until the
+/// running function returns.
+fn do_local(args: &[String], state: &mut ShellState, io: &mut BuiltinIo) -> i32 {
+    if !state.in_function() {
+        return ShellError::builtin("local", "can only be used in a function")
+            .report(&mut io.stderr);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !expand::is_valid_name(name) {
+            let message = format!("`{arg}': not a valid identifier");
+            status = ShellError::builtin("local", message).report(&mut io.stderr);
+            continue;
+        }
+        state.make_local(name);
+        if let Some(value) = value {
+            state.set_var(name, value);
         }
     }
+    status
 }
@@ -0,0 +1,374 @@
+use std::cmp::Ordering;
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use crate::arith;
+use crate::expand;
+use crate::state::ShellState;
+use crate::utils;
+
+/// Evaluates the arguments of `test`, or of `[` without its closing `]`.
+/// Up to four arguments are read as POSIX specifies, so that for example
+/// `test ! = x` compares strings; longer expressions are parsed with `!`,
+/// `-a`, `-o` and parentheses.
+pub fn test(args: &[String]) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    test_args(&args)
+}
+
+fn test_args(args: &[&str]) -> Result<bool, String> {
+    match args {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        [op, operand] if is_unary_operator(op) => unary_test(op, operand),
+        [lhs, "-a", rhs] => Ok(!lhs.is_empty() && !rhs.is_empty()),
+        [lhs, "-o", rhs] => Ok(!lhs.is_empty() || !rhs.is_empty()),
+        [lhs, op, rhs] if is_binary_operator(op) => binary_test(lhs, op, rhs),
+        ["!", rest @ ..] if args.len() <= 4 => Ok(!test_args(rest)?),
+        ["(", inner @ .., ")"] if args.len() <= 4 => test_args(inner),
+        _ => {
+            let mut parser = TestParser { args, pos: 0 };
+            let result = parser.parse_or()?;
+            match parser.args.get(parser.pos) {
+                Some(arg) => Err(format!("{arg}: too many arguments")),
+                None => Ok(result),
+            }
+        }
+    }
+}
+
+/// Parses and evaluates a `test` expression longer than four arguments.
+struct TestParser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl TestParser<'_> {
+    fn next(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.pos)?;
+        self.pos += 1;
+        Some(arg)
+    }
+
+    fn parse_or(&mut self) -> Result<bool, String> {
+        let mut result = self.parse_and()?;
+        while self.args.get(self.pos) == Some(&"-o") {
+            self.pos += 1;
+            result |= self.parse_and()?;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, String> {
+        let mut result = self.parse_not()?;
+        while self.args.get(self.pos) == Some(&"-a") {
+            self.pos += 1;
+            result &= self.parse_not()?;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, String> {
+        if self.args.get(self.pos) == Some(&"!") {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool, String> {
+        let Some(arg) = self.next() else {
+            return Err(String::from("argument expected"));
+        };
+        if arg == "(" {
+            let result = self.parse_or()?;
+            return match self.next() {
+                Some(")") => Ok(result),
+                _ => Err(String::from("`)' expected")),
+            };
+        }
+
+        let arg = arg.to_string();
+        match self.args.get(self.pos).copied() {
+            Some(op) if is_binary_operator(op) => {
+                self.pos += 1;
+                let rhs = self.next().ok_or(format!("{op}: argument expected"))?;
+                binary_test(&arg, op, rhs)
+            }
+            Some(operand) if is_unary_operator(&arg) => {
+                self.pos += 1;
+                unary_test(&arg, operand)
+            }
+            _ => Ok(!arg.is_empty()),
+        }
+    }
+}
+
+/// Evaluates the words of `[[ ... ]]`, expanding them without splitting
+/// as they are reached, so that `&&` and `||` short-circuit. The right of
+/// `==` and `!=` is a pattern, and that of `=~` an extended regular
+/// expression whose match and groups are stored in `BASH_REMATCH`.
+pub fn evaluate(words: &[String], state: &mut ShellState) -> Result<bool, String> {
+    let mut parser = ConditionalParser { words, pos: 0 };
+    let expression = parser.parse_or()?;
+    if let Some(word) = words.get(parser.pos) {
+        return Err(format!("syntax error near `{word}'"));
+    }
+    expression.evaluate(state)
+}
+
+/// A parsed `[[ ... ]]` expression, its operands still unexpanded.
+enum Expression<'a> {
+    Word(&'a str),
+    Unary(&'a str, &'a str),
+    Binary(&'a str, &'a str, &'a str),
+    Not(Box<Expression<'a>>),
+    And(Box<Expression<'a>>, Box<Expression<'a>>),
+    Or(Box<Expression<'a>>, Box<Expression<'a>>),
+}
+
+impl Expression<'_> {
+    fn evaluate(&self, state: &mut ShellState) -> Result<bool, String> {
+        match self {
+            Expression::Word(word) => Ok(!operand(word, state)?.is_empty()),
+            Expression::Unary(op, word) => unary_test(op, &operand(word, state)?),
+            Expression::Binary(lhs, op, rhs) => conditional_binary(lhs, op, rhs, state),
+            Expression::Not(expression) => Ok(!expression.evaluate(state)?),
+            Expression::And(lhs, rhs) => Ok(lhs.evaluate(state)? && rhs.evaluate(state)?),
+            Expression::Or(lhs, rhs) => Ok(lhs.evaluate(state)? || rhs.evaluate(state)?),
+        }
+    }
+}
+
+struct ConditionalParser<'a> {
+    words: &'a [String],
+    pos: usize,
+}
+
+impl<'a> ConditionalParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.peek()?;
+        self.pos += 1;
+        Some(word)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression<'a>, String> {
+        let mut expression = self.parse_and()?;
+        while self.peek() == Some("||") {
+            self.pos += 1;
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression<'a>, String> {
+        let mut expression = self.parse_not()?;
+        while self.peek() == Some("&&") {
+            self.pos += 1;
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_not()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression<'a>, String> {
+        if self.peek() == Some("!") {
+            self.pos += 1;
+            return Ok(Expression::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression<'a>, String> {
+        let word = match self.next() {
+            Some("&&" | "||" | ")") | None => {
+                return Err(String::from("unexpected end of conditional expression"));
+            }
+            Some(word) => word,
+        };
+        if word == "(" {
+            let expression = self.parse_or()?;
+            return match self.next() {
+                Some(")") => Ok(expression),
+                _ => Err(String::from("expected `)'")),
+            };
+        }
+
+        match self.peek() {
+            Some(op) if op == "=~" || is_binary_operator(op) => {
+                self.pos += 1;
+                match self.next() {
+                    Some(rhs) => Ok(Expression::Binary(word, op, rhs)),
+                    None => Err(format!("unexpected argument after `{op}'")),
+                }
+            }
+            Some(operand) if is_unary_operator(word) && !matches!(operand, "&&" | "||" | ")") => {
+                self.pos += 1;
+                Ok(Expression::Unary(word, operand))
+            }
+            _ => Ok(Expression::Word(word)),
+        }
+    }
+}
+
+/// Expands an operand without splitting. A failed expansion fails the
+/// whole test.
+fn operand(word: &str, state: &mut ShellState) -> Result<String, String> {
+    expand::expand_to_string(word, state).map_err(|error| error.to_string())
+}
+
+fn conditional_binary(
+    lhs: &str,
+    op: &str,
+    rhs: &str,
+    state: &mut ShellState,
+) -> Result<bool, String> {
+    let lhs = operand(lhs, state)?;
+    match op {
+        "=" | "==" | "!=" => {
+            let pattern = expand::expand_pattern(rhs, state).map_err(|error| error.to_string())?;
+            Ok(utils::pattern_matches(&pattern, &lhs) == (op != "!="))
+        }
+        "=~" => {
+            let regex = expand::expand_regex(rhs, state).map_err(|error| error.to_string())?;
+            let groups = utils::regex_match(&regex, &lhs)?;
+            let matched = groups.is_some();
+            state.set_array("BASH_REMATCH", groups.unwrap_or_default());
+            Ok(matched)
+        }
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            // Operands of `[[` are arithmetic expressions.
+            let lhs = arith::evaluate(&lhs, state)?;
+            let rhs = operand(rhs, state)?;
+            let rhs = arith::evaluate(&rhs, state)?;
+            Ok(compare_integers(lhs, op, rhs))
+        }
+        _ => binary_test(&lhs, op, &operand(rhs, state)?),
+    }
+}
+
+fn is_unary_operator(op: &str) -> bool {
+    op.len() == 2
+        && op.starts_with('-')
+        && op[1..]
+            .chars()
+            .all(|c| "bcdefghkLnprsStuwxzGNO".contains(c))
+}
+
+fn is_binary_operator(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "=="
+            | "!="
+            | "<"
+            | ">"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
+    )
+}
+
+fn unary_test(op: &str, operand: &str) -> Result<bool, String> {
+    let metadata = || fs::metadata(operand).ok();
+    let mode = |bits: u32| metadata().is_some_and(|metadata| metadata.mode() & bits != 0);
+
+    let result = match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-t" => match operand.trim().parse() {
+            Ok(fd) => utils::is_terminal(fd),
+            Err(_) => return Err(format!("{operand}: integer expression expected")),
+        },
+        "-e" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|metadata| metadata.is_file()),
+        "-d" => metadata().is_some_and(|metadata| metadata.is_dir()),
+        "-b" => metadata().is_some_and(|metadata| metadata.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|metadata| metadata.file_type().is_char_device()),
+        "-p" => metadata().is_some_and(|metadata| metadata.file_type().is_fifo()),
+        "-S" => metadata().is_some_and(|metadata| metadata.file_type().is_socket()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|metadata| metadata.is_symlink()),
+        "-s" => metadata().is_some_and(|metadata| metadata.len() > 0),
+        "-u" => mode(0o4000),
+        "-g" => mode(0o2000),
+        "-k" => mode(0o1000),
+        "-r" => accessible(operand, libc::R_OK),
+        "-w" => accessible(operand, libc::W_OK),
+        "-x" => accessible(operand, libc::X_OK),
+        "-O" => metadata().is_some_and(|metadata| metadata.uid() == unsafe { libc::geteuid() }),
+        "-G" => metadata().is_some_and(|metadata| metadata.gid() == unsafe { libc::getegid() }),
+        // Modified since it was last read.
+        "-N" => metadata().is_some_and(|metadata| metadata.mtime() > metadata.atime()),
+        _ => return Err(format!("{op}: unary operator expected")),
+    };
+    Ok(result)
+}
+
+fn binary_test(lhs: &str, op: &str, rhs: &str) -> Result<bool, String> {
+    let result = match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            compare_integers(parse_integer(lhs)?, op, parse_integer(rhs)?)
+        }
+        "-nt" | "-ot" | "-ef" => compare_files(lhs, op, rhs),
+        _ => return Err(format!("{op}: binary operator expected")),
+    };
+    Ok(result)
+}
+
+fn parse_integer(text: &str) -> Result<i64, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{text}: integer expression expected"))
+}
+
+fn compare_integers(lhs: i64, op: &str, rhs: i64) -> bool {
+    let ordering = lhs.cmp(&rhs);
+    match op {
+        "-eq" => ordering == Ordering::Equal,
+        "-ne" => ordering != Ordering::Equal,
+        "-lt" => ordering == Ordering::Less,
+        "-le" => ordering != Ordering::Greater,
+        "-gt" => ordering == Ordering::Greater,
+        _ => ordering != Ordering::Less,
+    }
+}
+
+/// `-nt` and `-ot` compare modification times, a missing file being older
+/// than any other, and `-ef` tells whether both are the same file.
+fn compare_files(lhs: &str, op: &str, rhs: &str) -> bool {
+    let (lhs, rhs) = (fs::metadata(lhs).ok(), fs::metadata(rhs).ok());
+    let modified = |metadata: &Option<Metadata>| {
+        metadata
+            .as_ref()
+            .map(|metadata| (metadata.mtime(), metadata.mtime_nsec()))
+    };
+
+    match op {
+        "-nt" => lhs.is_some() && modified(&lhs) > modified(&rhs),
+        "-ot" => rhs.is_some() && modified(&lhs) < modified(&rhs),
+        _ => match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.dev() == rhs.dev() && lhs.ino() == rhs.ino(),
+            _ => false,
+        },
+    }
+}
+
+fn accessible(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
@@ -2,12 +2,37 @@
 pub enum WriteFileMode {
     OverWrite,
     Append,
+    // `<` and `<&` only read from their target, `<>` opens it both ways.
+    Read,
+    ReadWrite,
+    // `<<` and `<<-`, whose target is the body of the here-document,
+    // expanded unless the delimiter was quoted.
+    HereDoc { expand: bool },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RedirectMode {
+    StdIn,
     StdOut,
     StdErr,
+    // Any other descriptor, as in `3>log`.
+    Fd(i32),
+}
+
+/// How a `case` item ends: `;;` stops, `;&` runs the next body as well
+/// and `;;&` goes on testing the following patterns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaseTerminator {
+    Break,
+    FallThrough,
+    Continue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<String>,
+    pub body: Vec<Command>,
+    pub terminator: CaseTerminator,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -24,4 +49,71 @@ pub enum Command {
         redirect_mode: RedirectMode,
         write_mode: WriteFileMode,
     },
+    Arithmetic {
+        expression: String,
+    },
+    // `[[ expression ]]`, its words kept unexpanded until it runs.
+    Conditional {
+        words: Vec<String>,
+    },
+    If {
+        // `if`/`elif` conditions with the body run when they succeed.
+        branches: Vec<(Vec<Command>, Vec<Command>)>,
+        else_body: Option<Vec<Command>>,
+    },
+    While {
+        condition: Vec<Command>,
+        body: Vec<Command>,
+    },
+    Until {
+        condition: Vec<Command>,
+        body: Vec<Command>,
+    },
+    For {
+        variable: String,
+        // `None` when the `in` list is omitted, iterating over `"$@"`.
+        words: Option<Vec<String>>,
+        body: Vec<Command>,
+    },
+    ArithmeticFor {
+        init: String,
+        condition: String,
+        step: String,
+        body: Vec<Command>,
+    },
+    Case {
+        word: String,
+        items: Vec<CaseItem>,
+    },
+    // `{ list; }`
+    Group {
+        body: Vec<Command>,
+    },
+    // `left && right` and `left || right`, each side a pipeline with its
+    // redirects; `a && b || c` nests `a && b` as the left side.
+    And {
+        left: Vec<Command>,
+        right: Vec<Command>,
+    },
+    Or {
+        left: Vec<Command>,
+        right: Vec<Command>,
+    },
+    // `list &`, run by a forked copy of the shell while the next command
+    // starts; `command` is its text, as `jobs` shows it.
+    Background {
+        body: Vec<Command>,
+        command: String,
+    },
+    // `( list )`, run by a forked copy of the shell.
+    Subshell {
+        body: Vec<Command>,
+    },
+    // `name() compound-command [redirects]`; the redirects apply to every
+    // call.
+    FunctionDef {
+        name: String,
+        body: Box<Command>,
+        redirects: Vec<Command>,
+    },
 }
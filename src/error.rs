@@ -0,0 +1,162 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use thiserror::Error;
+
+/// An error met while running commands. None of them ends the shell: the
+/// command that failed is reported on standard error, as bash does, and
+/// gets the exit status given by [`ShellError::status`].
+#[derive(Debug, Error)]
+pub enum ShellError {
+    /// Input that cannot be parsed as commands.
+    #[error(transparent)]
+    Syntax(#[from] SyntaxError),
+    #[error("{0}: command not found")]
+    CommandNotFound(String),
+    /// A name that cannot be assigned to, as that of a `for` loop.
+    #[error("`{0}': not a valid identifier")]
+    InvalidName(String),
+    /// An arithmetic expression that cannot be evaluated, such as one
+    /// dividing by zero.
+    #[error("{0}")]
+    Arithmetic(String),
+    /// A program that exists but could not be started.
+    #[error("{cmd}: {}", describe(.source))]
+    Spawn { cmd: String, source: io::Error },
+    /// A file or descriptor that a redirect could not open or duplicate.
+    #[error("{target}: {}", describe(.source))]
+    Redirect { target: String, source: io::Error },
+    /// Any other failed I/O, such as reading the history file or creating
+    /// a pipe, with what was being done in `context`.
+    #[error("{context}: {}", describe(.source))]
+    Io { context: String, source: io::Error },
+    /// A builtin invoked wrongly, failing with `status`.
+    #[error("{builtin}: {message}")]
+    Builtin {
+        builtin: String,
+        message: String,
+        status: i32,
+    },
+}
+
+impl ShellError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        ShellError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn builtin(builtin: &str, message: impl Into<String>) -> Self {
+        ShellError::Builtin {
+            builtin: builtin.to_string(),
+            message: message.into(),
+            status: 1,
+        }
+    }
+
+    /// The same error failing with `status`, if it is that of a builtin,
+    /// as for misuse, which fails with 2.
+    pub fn with_status(mut self, new_status: i32) -> Self {
+        if let ShellError::Builtin { status, .. } = &mut self {
+            *status = new_status;
+        }
+        self
+    }
+
+    /// The exit status of a command failing with this error: 2 for misuse,
+    /// 127 for a command that does not exist and 126 for one that cannot
+    /// be executed.
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::Syntax(_) => 2,
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::Spawn { source, .. } if source.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::Spawn { .. } => 126,
+            ShellError::InvalidName(_)
+            | ShellError::Arithmetic(_)
+            | ShellError::Redirect { .. }
+            | ShellError::Io { .. } => 1,
+            ShellError::Builtin { status, .. } => *status,
+        }
+    }
+
+    /// Writes the error to `stderr` and returns the status of the failed
+    /// command.
+    pub fn report(&self, stderr: &mut dyn Write) -> i32 {
+        let _ = writeln!(stderr, "{self}");
+        self.status()
+    }
+}
+
+/// Input the parser rejects, with the byte offsets of the offending token
+/// in it.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{kind}")]
+pub struct SyntaxError {
+    pub kind: SyntaxErrorKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SyntaxErrorKind {
+    /// A token where the grammar does not allow it, such as `|` starting a
+    /// command. A missing redirect target is reported as `newline`.
+    #[error("syntax error near unexpected token `{0}'")]
+    UnexpectedToken(String),
+    /// Input ending within a compound command or a pipeline.
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof,
+    /// Input ending within quotes or a substitution, with the text that
+    /// would close it.
+    #[error("unexpected EOF while looking for matching `{0}'")]
+    Unterminated(String),
+}
+
+impl SyntaxError {
+    pub fn new(kind: SyntaxErrorKind, span: Range<usize>) -> Self {
+        SyntaxError { kind, span }
+    }
+
+    /// Whether more input could make the input valid, in which case the
+    /// shell may read more lines rather than reject it.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self.kind,
+            SyntaxErrorKind::UnexpectedEof | SyntaxErrorKind::Unterminated(_)
+        )
+    }
+
+    /// The message followed by the line of `input` holding the error, with
+    /// carets under the offending token, as shown at the prompt.
+    pub fn render(&self, input: &str) -> String {
+        let input = input.trim_end_matches('\n');
+        let start = self.span.start.min(input.len());
+        let line_start = input[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = input[start..]
+            .find('\n')
+            .map_or(input.len(), |idx| start + idx);
+        let end = self.span.end.clamp(start, line_end);
+
+        // Tabs are kept so that the carets line up however they display.
+        let padding: String = input[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(input[start..end].chars().count().max(1));
+        format!(
+            "{self}\n{}\n{padding}{carets}\n",
+            &input[line_start..line_end]
+        )
+    }
+}
+
+/// The message of an I/O error without the `(os error N)` Rust appends,
+/// as in `No such file or directory`.
+pub fn describe(error: &io::Error) -> String {
+    let message = error.to_string();
+    match message.split_once(" (os error") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
@@ -0,0 +1,641 @@
+use std::env;
+
+use crate::arith;
+use crate::error::ShellError;
+use crate::interpret;
+use crate::parse;
+use crate::printf;
+use crate::state::ShellState;
+use crate::utils;
+
+/// Expands a word as stored by the parser into the fields it produces:
+/// tilde prefixes, parameters, arithmetic, command and process
+/// substitutions are resolved, unquoted results are split into fields on
+/// the characters of `IFS`, and quotes are removed. An expansion that
+/// fails, such as a division by zero, fails the whole word.
+pub fn expand_word(word: &str, state: &mut ShellState) -> Result<Vec<String>, ShellError> {
+    let mut expander = Expander::new(word, true, state);
+    expander.run();
+    expander.finish()
+}
+
+/// Expands a word in a context where no field splitting happens, such as
+/// a redirect target or the value of an assignment.
+pub fn expand_to_string(word: &str, state: &mut ShellState) -> Result<String, ShellError> {
+    let mut expander = Expander::new(word, false, state);
+    expander.run();
+    Ok(expander.finish()?.join(" "))
+}
+
+/// Expands a glob pattern, such as a `case` pattern, without splitting.
+/// Quoted characters come out backslash-escaped, so they only match
+/// themselves.
+pub fn expand_pattern(word: &str, state: &mut ShellState) -> Result<String, ShellError> {
+    let mut expander = Expander::new(word, false, state);
+    expander.pattern = true;
+    expander.run();
+    Ok(expander.finish()?.join(" "))
+}
+
+/// Expands the regular expression of `[[ word =~ regex ]]` without
+/// splitting. Quoted characters come out backslash-escaped, so they only
+/// match themselves.
+pub fn expand_regex(word: &str, state: &mut ShellState) -> Result<String, ShellError> {
+    let mut expander = Expander::new(word, false, state);
+    expander.regex = true;
+    expander.run();
+    Ok(expander.finish()?.join(" "))
+}
+
+/// Expands an arithmetic expression, as that of `$((...))` or `((...))`:
+/// as if within double quotes, except that double quotes are removed.
+pub fn expand_arithmetic(expression: &str, state: &mut ShellState) -> Result<String, ShellError> {
+    let mut expander = Expander::new(expression, false, state);
+    while expander.peek().is_some() {
+        expander.expand_double_quoted();
+    }
+    Ok(expander.finish()?.join(" "))
+}
+
+/// Expands the body of a here-document whose delimiter was not quoted:
+/// as if within double quotes, except that double quotes are kept and a
+/// backslash escapes only `$`, `` ` ``, `\` and newline.
+pub fn expand_here_doc(body: &str, state: &mut ShellState) -> Result<String, ShellError> {
+    let mut expander = Expander::new(body, false, state);
+    expander.expand_here_doc();
+    Ok(expander.finish()?.join(" "))
+}
+
+struct Expander<'a> {
+    state: &'a mut ShellState,
+    chars: Vec<char>,
+    pos: usize,
+    split: bool,
+    pattern: bool,
+    regex: bool,
+    assignment: bool,
+    fields: Vec<String>,
+    current: String,
+    // A field exists (possibly empty, as in `""`) and must be kept.
+    started: bool,
+    // The first expansion that failed, after which the rest of the word
+    // is skipped.
+    error: Option<ShellError>,
+}
+
+impl<'a> Expander<'a> {
+    fn new(word: &str, split: bool, state: &'a mut ShellState) -> Self {
+        Expander {
+            state,
+            chars: word.chars().collect(),
+            pos: 0,
+            split,
+            pattern: false,
+            regex: false,
+            assignment: is_assignment(word),
+            fields: vec![],
+            current: String::new(),
+            started: false,
+            error: None,
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<String>, ShellError> {
+        if self.started {
+            self.fields.push(std::mem::take(&mut self.current));
+        }
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.fields),
+        }
+    }
+
+    /// Records a failed expansion and stops expanding the word, so that no
+    /// further substitution runs.
+    fn fail(&mut self, error: ShellError) {
+        self.error.get_or_insert(error);
+        self.pos = self.chars.len();
+    }
+
+    fn run(&mut self) {
+        self.expand_tilde_prefix();
+
+        while let Some(c) = self.next_char() {
+            match c {
+                '\'' => {
+                    let quoted = self.take_until('\'');
+                    self.push_quoted_str(&quoted);
+                }
+                '"' => self.expand_double_quoted(),
+                '\\' => {
+                    if let Some(escaped) = self.next_char() {
+                        self.push_quoted_char(escaped);
+                    }
+                }
+                '$' if self.peek() == Some('\'') => {
+                    self.pos += 1;
+                    let quoted = self.take_ansi_c_quoted();
+                    self.push_quoted_str(&quoted);
+                }
+                '$' if self.take_all_positional().is_some() => self.push_positional_unquoted(),
+                '$' => {
+                    let expansion = self.dollar_expansion();
+                    self.push_expansion(&expansion, self.split);
+                }
+                '`' => {
+                    let output = self.backtick_substitution(false);
+                    self.push_expansion(&output, self.split);
+                }
+                '<' | '>' if self.peek() == Some('(') => {
+                    self.pos += 1;
+                    let body = self.take_parenthesized();
+                    let path = interpret::process_substitution(&body, c == '<', self.state);
+                    self.push_str(&path);
+                }
+                '=' | ':' if self.assignment => {
+                    self.push_char(c);
+                    self.expand_tilde_prefix();
+                }
+                _ => self.push_char(c),
+            }
+        }
+    }
+
+    fn expand_double_quoted(&mut self) {
+        // `""` is an empty field, but `"$@"` without positional parameters
+        // expands to no field at all.
+        let mut saw_positional = false;
+
+        while let Some(c) = self.next_char() {
+            match c {
+                '"' => break,
+                '\\' => match self.next_char() {
+                    Some(escaped @ ('$' | '`' | '"' | '\\')) => self.push_quoted_char(escaped),
+                    Some(other) => {
+                        self.push_quoted_char('\\');
+                        self.push_quoted_char(other);
+                    }
+                    None => self.push_quoted_char('\\'),
+                },
+                '$' => match self.take_all_positional() {
+                    Some('@') => {
+                        saw_positional = true;
+                        self.push_positional_quoted();
+                    }
+                    Some(_) => {
+                        let joined = self.parameter("*");
+                        self.push_quoted_str(&joined);
+                    }
+                    None => {
+                        let expansion = self.dollar_expansion();
+                        self.push_quoted_str(&expansion);
+                    }
+                },
+                '`' => {
+                    let output = self.backtick_substitution(true);
+                    self.push_quoted_str(&output);
+                }
+                _ => self.push_quoted_char(c),
+            }
+        }
+
+        if !saw_positional {
+            self.started = true;
+        }
+    }
+
+    fn expand_here_doc(&mut self) {
+        while let Some(c) = self.next_char() {
+            match c {
+                '\\' => match self.next_char() {
+                    Some('\n') => {}
+                    Some(escaped @ ('$' | '`' | '\\')) => self.push_quoted_char(escaped),
+                    Some(other) => {
+                        self.push_quoted_char('\\');
+                        self.push_quoted_char(other);
+                    }
+                    None => self.push_quoted_char('\\'),
+                },
+                '$' => {
+                    let expansion = self.dollar_expansion();
+                    self.push_quoted_str(&expansion);
+                }
+                '`' => {
+                    let output = self.backtick_substitution(true);
+                    self.push_quoted_str(&output);
+                }
+                _ => self.push_quoted_char(c),
+            }
+        }
+        self.started = true;
+    }
+
+    /// Consumes the `@` or `*` of `$@`, `$*`, `${@}` or `${*}` after a `$`,
+    /// returning which it was.
+    fn take_all_positional(&mut self) -> Option<char> {
+        let (c, len) = match self.chars[self.pos..] {
+            [c @ ('@' | '*'), ..] => (c, 1),
+            ['{', c @ ('@' | '*'), '}', ..] => (c, 3),
+            _ => return None,
+        };
+        self.pos += len;
+        Some(c)
+    }
+
+    /// `"$@"`: every positional parameter becomes its own field.
+    fn push_positional_quoted(&mut self) {
+        if !self.split {
+            let joined = self.state.positional.join(" ");
+            self.push_str(&joined);
+            return;
+        }
+
+        let params = self.state.positional.clone();
+        for (idx, param) in params.iter().enumerate() {
+            if idx > 0 {
+                self.fields.push(std::mem::take(&mut self.current));
+            }
+            self.push_str(param);
+        }
+    }
+
+    /// Unquoted `$@` and `$*`: every positional parameter is split on its
+    /// own, and parameters never join into a common field.
+    fn push_positional_unquoted(&mut self) {
+        let params = self.state.positional.clone();
+        for (idx, param) in params.iter().enumerate() {
+            if idx > 0 {
+                if self.split {
+                    self.delimit_field();
+                } else {
+                    self.push_char(' ');
+                }
+            }
+            self.push_expansion(param, self.split);
+        }
+    }
+
+    /// Replaces an unquoted tilde prefix (`~`, `~user`, `~+`, `~-`) at the
+    /// current position. Unknown users and unset variables leave it as is.
+    fn expand_tilde_prefix(&mut self) {
+        if self.peek() != Some('~') {
+            return;
+        }
+
+        let rest = &self.chars[self.pos + 1..];
+        let prefix_len = rest
+            .iter()
+            .position(|&c| c == '/' || (self.assignment && c == ':'))
+            .unwrap_or(rest.len());
+        let prefix: String = rest[..prefix_len].iter().collect();
+
+        // A quoted character anywhere in the prefix disables the expansion.
+        if prefix.contains(['\'', '"', '\\', '$', '`']) {
+            return;
+        }
+
+        let var = |name: &str| self.state.get_var(name).map(String::from);
+        let home = match prefix.as_str() {
+            "" => var("HOME").or_else(utils::current_user_home),
+            "+" => var("PWD").or_else(|| {
+                env::current_dir()
+                    .ok()
+                    .map(|dir| dir.to_string_lossy().into_owned())
+            }),
+            "-" => var("OLDPWD"),
+            user => utils::home_dir_of(user),
+        };
+
+        if let Some(home) = home {
+            self.pos += 1 + prefix_len;
+            self.push_str(&home);
+        }
+    }
+
+    /// Expands what follows a `$`: `$((...))`, `$(...)`, `${name}`, `$name`
+    /// or a special parameter. A lone `$` stays literal.
+    fn dollar_expansion(&mut self) -> String {
+        match self.peek() {
+            Some('(') if self.chars.get(self.pos + 1) == Some(&'(') => {
+                self.pos += 1;
+                self.arithmetic_expansion()
+            }
+            Some('(') => {
+                self.pos += 1;
+                let body = self.take_parenthesized();
+                run_substitution(&body, self.state)
+            }
+            Some('{') => {
+                self.pos += 1;
+                let name = self.take_until('}');
+                self.parameter(&name)
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c == '_' || c.is_ascii_alphanumeric())
+                {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                self.parameter(&name)
+            }
+            Some(c @ ('?' | '$' | '!' | '#' | '*' | '@' | '0'..='9')) => {
+                self.pos += 1;
+                self.parameter(&c.to_string())
+            }
+            _ => "$".to_string(),
+        }
+    }
+
+    fn parameter(&self, name: &str) -> String {
+        match name {
+            "?" => self.state.last_status.to_string(),
+            "$" => self.state.shell_pid.to_string(),
+            "!" => self
+                .state
+                .last_background_pid
+                .map(|pid| pid.to_string())
+                .unwrap_or_default(),
+            "#" => self.state.positional.len().to_string(),
+            "@" => self.state.positional.join(" "),
+            // `"$*"` joins with the first character of IFS.
+            "*" => {
+                let separator = match self.state.get_var("IFS") {
+                    Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    None => " ".to_string(),
+                };
+                self.state.positional.join(&separator)
+            }
+            _ => match name.parse::<usize>() {
+                Ok(0) => self.state.script_name.clone(),
+                Ok(idx) => self
+                    .state
+                    .positional
+                    .get(idx - 1)
+                    .cloned()
+                    .unwrap_or_default(),
+                Err(_) => self.variable(name),
+            },
+        }
+    }
+
+    /// A variable or array element: `name`, `name[N]`, `name[@]`, and
+    /// their lengths `#name` and `#name[@]`.
+    fn variable(&self, name: &str) -> String {
+        if let Some(name) = name.strip_prefix('#').filter(|name| !name.is_empty()) {
+            return match name.strip_suffix("[@]").or(name.strip_suffix("[*]")) {
+                Some(array) => self.state.get_array(array).map_or(0, <[String]>::len),
+                None => self.variable(name).chars().count(),
+            }
+            .to_string();
+        }
+
+        let (name, index) = match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            Some((name, index)) => (name, index),
+            None => (name, "0"),
+        };
+        match self.state.get_array(name) {
+            Some(values) if index == "@" || index == "*" => values.join(" "),
+            Some(values) => index
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| values.get(index))
+                .cloned()
+                .unwrap_or_default(),
+            None if index == "0" => self.state.get_var(name).unwrap_or("").to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Evaluates a `$((...))`, positioned on its second opening parenthesis.
+    /// The body goes through parameter expansion and command substitution
+    /// before being evaluated.
+    fn arithmetic_expansion(&mut self) -> String {
+        let body = self.take_parenthesized();
+        let body = body.strip_suffix(')').unwrap_or(&body);
+        let body = body.strip_prefix('(').unwrap_or(body);
+
+        let value = expand_arithmetic(body, self.state).and_then(|expression| {
+            arith::evaluate(&expression, self.state).map_err(ShellError::Arithmetic)
+        });
+        match value {
+            Ok(value) => value.to_string(),
+            Err(error) => {
+                self.fail(error);
+                String::new()
+            }
+        }
+    }
+
+    /// Takes the text up to the `)` matching an opening parenthesis the
+    /// position is right after, and consumes that `)`.
+    fn take_parenthesized(&mut self) -> String {
+        let start = self.pos;
+        let end = find_closing_paren(&self.chars, start);
+        let body: String = self.chars[start..end].iter().collect();
+        self.pos = (end + 1).min(self.chars.len());
+        body
+    }
+
+    /// Runs the body of a legacy `` `...` `` substitution. Backslashes only
+    /// escape `$`, `` ` `` and `\` in there (and `"` within double quotes).
+    fn backtick_substitution(&mut self, in_double_quotes: bool) -> String {
+        let mut body = String::new();
+
+        while let Some(c) = self.next_char() {
+            match c {
+                '`' => break,
+                '\\' => match self.next_char() {
+                    Some(escaped @ ('$' | '`' | '\\')) => body.push(escaped),
+                    Some('"') if in_double_quotes => body.push('"'),
+                    Some(other) => {
+                        body.push('\\');
+                        body.push(other);
+                    }
+                    None => body.push('\\'),
+                },
+                _ => body.push(c),
+            }
+        }
+        run_substitution(&body, self.state)
+    }
+
+    /// Appends the result of an expansion, splitting it into fields on IFS
+    /// when it is unquoted. IFS whitespace delimits fields and is trimmed,
+    /// while every other IFS character ends exactly one, possibly empty,
+    /// field.
+    fn push_expansion(&mut self, text: &str, split: bool) {
+        // An empty unquoted expansion adds nothing, not even an empty field.
+        if text.is_empty() {
+            return;
+        }
+        let ifs = self.state.get_var("IFS").unwrap_or(DEFAULT_IFS).to_string();
+        if !split || ifs.is_empty() {
+            self.push_str(text);
+            return;
+        }
+
+        let mut after_whitespace = false;
+        for c in text.chars() {
+            if !ifs.contains(c) {
+                self.push_char(c);
+                after_whitespace = false;
+            } else if is_ifs_whitespace(c) {
+                if self.started {
+                    self.delimit_field();
+                    after_whitespace = true;
+                }
+            } else {
+                if self.started {
+                    self.delimit_field();
+                } else if !after_whitespace {
+                    self.fields.push(String::new());
+                }
+                after_whitespace = false;
+            }
+        }
+    }
+
+    fn delimit_field(&mut self) {
+        if self.started {
+            self.fields.push(std::mem::take(&mut self.current));
+            self.started = false;
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.current.push(c);
+        self.started = true;
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.current.push_str(text);
+        self.started = true;
+    }
+
+    /// Quoted text, escaped when it is part of a pattern.
+    fn push_quoted_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.push_quoted_char(c);
+        }
+        self.started = true;
+    }
+
+    fn push_quoted_char(&mut self, c: char) {
+        if self.pattern && matches!(c, '*' | '?' | '[' | ']' | '\\')
+            || self.regex && "\\.[]()*+?{}|^$".contains(c)
+        {
+            self.current.push('\\');
+        }
+        self.push_char(c);
+    }
+
+    fn take_until(&mut self, end: char) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.next_char() {
+            if c == end {
+                break;
+            }
+            taken.push(c);
+        }
+        taken
+    }
+
+    /// Takes the rest of a `$'...'` string, with its escapes expanded.
+    fn take_ansi_c_quoted(&mut self) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.next_char() {
+            match c {
+                '\'' => break,
+                '\\' => {
+                    taken.push(c);
+                    taken.extend(self.next_char());
+                }
+                _ => taken.push(c),
+            }
+        }
+        String::from_utf8_lossy(&printf::expand_ansi_c(&taken)).into_owned()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+}
+
+pub const DEFAULT_IFS: &str = " \t\n";
+
+fn is_ifs_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n'
+}
+
+/// Finds the `)` matching an already opened `(`, skipping over quoted
+/// text and nested parentheses. Returns the input length when unbalanced.
+fn find_closing_paren(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut idx = start;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => idx += 1,
+            // `$` stands for the quote of `$'...'`, in which `\'` is escaped.
+            (Some('$'), '\'') => quote = None,
+            (Some('$'), _) => {}
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '\'') if idx > start && chars[idx - 1] == '$' => quote = Some('$'),
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return idx,
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+        idx += 1;
+    }
+    chars.len()
+}
+
+/// Parses and runs `body` with its standard output captured; trailing
+/// newlines are dropped as they would be by any POSIX shell. Its status
+/// becomes `$?`.
+fn run_substitution(body: &str, state: &mut ShellState) -> String {
+    let (output, status) = match parse::run_parser(body) {
+        Ok(commands) => interpret::capture_output(commands, state),
+        Err(error) => (vec![], interpret::report(error.into())),
+    };
+    state.last_status = status;
+    state.substitution_status = Some(status);
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    output.truncate(output.trim_end_matches('\n').len());
+    output
+}
+
+/// Whether `word` has the `NAME=value` shape of an assignment.
+pub fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => is_valid_name(name),
+        None => false,
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
@@ -1,212 +1,1034 @@
-use std::io::{self, Write};
-use std::io::{BufRead, BufReader};
-use std::process::{Command as ProcessCommand, Output, Stdio};
-use std::thread;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
 
-use crate::enums::{Command, RedirectMode, WriteFileMode};
+use crate::arith;
+use crate::builtins::BuiltinIo;
+use crate::commands;
+use crate::conditional;
+use crate::enums::{CaseItem, CaseTerminator, Command, RedirectMode, WriteFileMode};
+use crate::error::ShellError;
+use crate::expand;
+use crate::jobs;
+use crate::parse;
+use crate::state::{FlowControl, ShellState};
 use crate::utils;
 
-pub fn interpret_command(commands: Vec<Command>) {
-    let mut last_output: Option<Output> = None;
+type EnvVars = Vec<(String, String)>;
 
-    for command in commands {
-        match command {
-            Command::Program { cmd, args } => {
-                last_output = execute_program(&cmd, args);
+/// Runs a parsed command line and returns the exit status of the last
+/// command. Output goes straight to the shell's standard streams; any
+/// `RedirectTo` following a command applies to that command only.
+pub fn interpret_command(commands: Vec<Command>, state: &mut ShellState) -> i32 {
+    execute_list(&commands, state)
+}
+
+/// Runs a whole script, parsing and running one complete command at a
+/// time, until its end, `exit` or a syntax error. Returns the status of the
+/// last command.
+pub fn run_script(script: &str, state: &mut ShellState) -> i32 {
+    let mut chunk = String::new();
+
+    for line in script.split_inclusive('\n') {
+        chunk.push_str(line);
+        if parse::is_incomplete(&chunk) {
+            continue;
+        }
+        if !run_chunk(&chunk, state) || state.flow_control.is_some() {
+            return state.last_status;
+        }
+        chunk.clear();
+    }
+
+    if !chunk.is_empty() {
+        run_chunk(&chunk, state);
+    }
+    state.last_status
+}
+
+/// Parses and runs complete commands, returning false on a syntax error,
+/// which is shown with the offending line in an interactive shell.
+fn run_chunk(chunk: &str, state: &mut ShellState) -> bool {
+    match parse::run_parser(chunk) {
+        Ok(commands) => {
+            interpret_command(commands, state);
+            true
+        }
+        Err(error) if state.options.interactive => {
+            eprint!("{}", error.render(chunk));
+            state.last_status = ShellError::from(error).status();
+            false
+        }
+        Err(error) => {
+            state.last_status = report(error.into());
+            false
+        }
+    }
+}
+
+/// Reports an error on the shell's standard error, returning the status
+/// of the command that failed.
+pub fn report(error: ShellError) -> i32 {
+    error.report(&mut io::stderr())
+}
+
+/// Runs the commands of a file in the current shell, as startup files and
+/// `.` do.
+pub fn run_file(path: &Path, state: &mut ShellState) -> io::Result<i32> {
+    let script = fs::read_to_string(path)?;
+    Ok(run_script(&script, state))
+}
+
+fn execute_list(commands: &[Command], state: &mut ShellState) -> i32 {
+    let mut status = 0;
+    let mut idx = 0;
+
+    // A pending `break`/`continue` skips the rest of the list.
+    while idx < commands.len() && state.flow_control.is_none() {
+        let command = match &commands[idx] {
+            Command::RedirectTo { .. } => None,
+            command => {
+                idx += 1;
+                Some(command)
             }
-            Command::Pipe { programs } => {
-                let _ = execute_pipeline(programs);
+        };
+        let redirect_count = commands[idx..]
+            .iter()
+            .take_while(|cmd| matches!(cmd, Command::RedirectTo { .. }))
+            .count();
+        let redirects = &commands[idx..idx + redirect_count];
+        idx += redirect_count;
+
+        status = execute_with_redirects(command, redirects, state);
+        state.last_status = status;
+    }
+    status
+}
+
+/// Runs `right` only if `left` succeeds, for `&&`, or fails, for `||`.
+fn execute_and_or(left: &[Command], right: &[Command], and: bool, state: &mut ShellState) -> i32 {
+    let status = execute_list(left, state);
+    if state.flow_control.is_some() || (status == 0) != and {
+        return status;
+    }
+    state.last_status = status;
+    execute_list(right, state)
+}
+
+/// Runs `commands` in a forked child whose standard output is collected,
+/// as needed by command substitution. Returns the output with the
+/// child's status.
+pub fn capture_output(commands: Vec<Command>, state: &mut ShellState) -> (Vec<u8>, i32) {
+    let _ = io::stdout().flush();
+    let (mut reader, writer) = match utils::pipe() {
+        Ok(fds) => fds,
+        Err(error) => return (vec![], report(ShellError::io("pipe", error))),
+    };
+
+    // The closure owns the write end, so the parent's copy is closed as
+    // soon as the fork returns and `read_to_end` sees EOF.
+    let pid = utils::fork_with(move || {
+        utils::redirect_fd(&writer, libc::STDOUT_FILENO);
+        drop(writer);
+        interpret_command(commands, state)
+    });
+
+    let mut output = vec![];
+    let _ = reader.read_to_end(&mut output);
+    let status = pid.map_or(1, utils::wait_pid);
+    (output, status)
+}
+
+/// Starts `body` with its stdout (for `<(...)`, `reading`) or stdin (for
+/// `>(...)`) connected to a pipe, and returns the `/dev/fd` path of the
+/// shell's end of that pipe.
+pub fn process_substitution(body: &str, reading: bool, state: &mut ShellState) -> String {
+    let commands = match parse::run_parser(body) {
+        Ok(commands) => commands,
+        Err(error) => {
+            report(error.into());
+            return String::new();
+        }
+    };
+    let _ = io::stdout().flush();
+    let (reader, writer) = match utils::pipe() {
+        Ok(fds) => fds,
+        Err(error) => {
+            report(ShellError::io("pipe", error));
+            return String::new();
+        }
+    };
+    let (kept, given, target) = if reading {
+        (reader, writer, libc::STDOUT_FILENO)
+    } else {
+        (writer, reader, libc::STDIN_FILENO)
+    };
+    let kept_fd = kept.as_raw_fd();
+    let child_state = &mut *state;
+
+    let pid = utils::fork_with(move || {
+        utils::close_fd(kept_fd);
+        for (fd, _) in child_state.process_substitutions.drain(..) {
+            utils::close_fd(fd);
+        }
+        utils::redirect_fd(&given, target);
+        drop(given);
+        interpret_command(commands, child_state)
+    });
+
+    let kept_fd = kept.into_raw_fd();
+    utils::clear_cloexec(kept_fd);
+    if let Some(pid) = pid {
+        state.process_substitutions.push((kept_fd, pid));
+    }
+    format!("/dev/fd/{kept_fd}")
+}
+
+/// Closes the shell's ends of the process substitution pipes opened since
+/// the first `kept` ones, so that the substituted processes see EOF or
+/// SIGPIPE, and waits for them. Those of enclosing commands stay open.
+fn reap_process_substitutions(kept: usize, state: &mut ShellState) {
+    let opened: Vec<_> = state.process_substitutions.drain(kept..).collect();
+    for (fd, pid) in opened {
+        utils::close_fd(fd);
+        utils::wait_pid(pid);
+    }
+}
+
+/// Expands the words of `command` first, then runs it with `redirects`
+/// applied, the order in which a POSIX shell performs these steps.
+fn execute_with_redirects(
+    command: Option<&Command>,
+    redirects: &[Command],
+    state: &mut ShellState,
+) -> i32 {
+    let enclosing_substitutions = state.process_substitutions.len();
+    let status = match command {
+        Some(Command::Program { cmd, args }) => match expand_simple_command(cmd, args, state) {
+            // Like the redirects, the assignments of a bare `exec` stay.
+            Ok((assignments, fields)) if fields == ["exec"] => {
+                for (name, value) in assignments {
+                    state.set_var(&name, &value);
+                }
+                execute_permanent_redirects(redirects, state)
             }
-            Command::RedirectTo {
-                path,
-                redirect_mode,
-                write_mode,
-            } => {
-                if let Some(output) = last_output.take() {
-                    handle_redirect(output, path, redirect_mode, write_mode);
+            Ok((assignments, fields)) => with_redirects(redirects, state, |state| {
+                execute_simple_command(assignments, fields, state)
+            }),
+            Err(error) => report(error),
+        },
+        Some(Command::Pipe { programs }) => {
+            let stages: Result<Vec<Stage>, ShellError> = programs
+                .iter()
+                .map(|program| match program.as_ref() {
+                    Command::Program { cmd, args } => {
+                        let (assignments, fields) = expand_simple_command(cmd, args, state)?;
+                        Ok(Stage::Simple(assignments, fields))
+                    }
+                    compound => Ok(Stage::Compound(compound)),
+                })
+                .collect();
+            match stages {
+                Ok(stages) => {
+                    with_redirects(redirects, state, |state| execute_pipeline(stages, state))
                 }
+                Err(error) => report(error),
+            }
+        }
+        Some(Command::Arithmetic { expression }) => with_redirects(redirects, state, |state| {
+            execute_arithmetic(expression, state)
+        }),
+        Some(Command::Conditional { words }) => {
+            with_redirects(redirects, state, |state| execute_conditional(words, state))
+        }
+        Some(Command::If {
+            branches,
+            else_body,
+        }) => with_redirects(redirects, state, |state| {
+            execute_if(branches, else_body.as_deref(), state)
+        }),
+        Some(Command::While { condition, body }) => with_redirects(redirects, state, |state| {
+            execute_while(condition, body, true, state)
+        }),
+        Some(Command::Until { condition, body }) => with_redirects(redirects, state, |state| {
+            execute_while(condition, body, false, state)
+        }),
+        Some(Command::For {
+            variable,
+            words,
+            body,
+        }) => {
+            let items = match words {
+                Some(words) => words
+                    .iter()
+                    .map(|word| expand::expand_word(word, state))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|fields| fields.concat()),
+                None => Ok(state.positional.clone()),
+            };
+            match items {
+                Ok(items) => with_redirects(redirects, state, |state| {
+                    execute_for(variable, items, body, state)
+                }),
+                Err(error) => report(error),
             }
         }
+        Some(Command::ArithmeticFor {
+            init,
+            condition,
+            step,
+            body,
+        }) => with_redirects(redirects, state, |state| {
+            execute_arithmetic_for([init, condition, step], body, state)
+        }),
+        Some(Command::Case { word, items }) => {
+            with_redirects(redirects, state, |state| execute_case(word, items, state))
+        }
+        Some(Command::Subshell { body }) => {
+            with_redirects(redirects, state, |state| execute_subshell(body, state))
+        }
+        Some(Command::Group { body }) => {
+            with_redirects(redirects, state, |state| execute_list(body, state))
+        }
+        Some(Command::Background { body, command }) => jobs::start(body, command, state),
+        Some(Command::And { left, right }) => execute_and_or(left, right, true, state),
+        Some(Command::Or { left, right }) => execute_and_or(left, right, false, state),
+        Some(definition @ Command::FunctionDef { name, .. }) => {
+            state.functions.insert(name.clone(), definition.clone());
+            with_redirects(redirects, state, |_| 0)
+        }
+        _ => with_redirects(redirects, state, |_| 0),
+    };
+
+    reap_process_substitutions(enclosing_substitutions, state);
+    status
+}
+
+fn with_redirects<F>(redirects: &[Command], state: &mut ShellState, run: F) -> i32
+where
+    F: FnOnce(&mut ShellState) -> i32,
+{
+    let _ = io::stdout().flush();
+    let saved = match apply_redirects(redirects, state) {
+        Ok(saved) => saved,
+        Err(error) => return report(error),
+    };
+
+    let status = run(state);
+
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    for (fd, saved_fd) in saved.into_iter().rev() {
+        match saved_fd {
+            Some(saved_fd) => utils::redirect_fd(&saved_fd, fd),
+            None => utils::close_fd(fd),
+        }
     }
-    if let Some(output) = last_output {
-        write_output(&output.stdout);
+    status
+}
+
+/// `exec` without a command: its redirects stay in place for the rest of
+/// the shell's life.
+fn execute_permanent_redirects(redirects: &[Command], state: &mut ShellState) -> i32 {
+    let _ = io::stdout().flush();
+    match apply_redirects(redirects, state) {
+        Ok(_) => 0,
+        Err(error) => report(error),
     }
 }
 
-fn execute_program(cmd: &str, args: Vec<String>) -> Option<Output> {
-    match utils::find_executable_on_path(cmd) {
-        Some(program_path) => {
-            let program_executable = program_path.file_name().unwrap().to_str().unwrap();
-            let program_args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-            Some(utils::run_cmd(program_executable, &program_args))
+/// Points the shell's own descriptors at the redirect targets, returning
+/// copies of the previous ones, if open, so they can be restored.
+fn apply_redirects(
+    redirects: &[Command],
+    state: &mut ShellState,
+) -> Result<Vec<(i32, Option<OwnedFd>)>, ShellError> {
+    let mut saved = vec![];
+
+    for redirect in redirects {
+        if let Command::RedirectTo {
+            path,
+            redirect_mode,
+            write_mode,
+        } = redirect
+        {
+            let path = match write_mode {
+                WriteFileMode::HereDoc { expand: false } => path.clone(),
+                WriteFileMode::HereDoc { expand: true } => expand::expand_here_doc(path, state)?,
+                _ => expand::expand_to_string(path, state)?,
+            };
+            let fd = match redirect_mode {
+                RedirectMode::StdIn => libc::STDIN_FILENO,
+                RedirectMode::StdOut => libc::STDOUT_FILENO,
+                RedirectMode::StdErr => libc::STDERR_FILENO,
+                RedirectMode::Fd(fd) => *fd,
+            };
+            let saved_fd = utils::save_fd(fd).map_err(|source| ShellError::Redirect {
+                target: fd.to_string(),
+                source,
+            })?;
+            saved.push((fd, saved_fd));
+
+            // `>&-` and `<&-` close the descriptor, `>&N` and `<&N`
+            // duplicate another one instead of opening a file.
+            if path == "&-" {
+                utils::close_fd(fd);
+                continue;
+            }
+            if let Some(source_fd) = path.strip_prefix('&').and_then(|n| n.parse::<i32>().ok()) {
+                utils::duplicate_fd(source_fd, fd).map_err(|source| ShellError::Redirect {
+                    target: source_fd.to_string(),
+                    source,
+                })?;
+                continue;
+            }
+            let file = match write_mode {
+                WriteFileMode::HereDoc { .. } => utils::here_doc_file(&path)
+                    .map_err(|error| ShellError::io("here-document", error))?,
+                _ => utils::open_for_redirect(&path, write_mode.clone()).map_err(|source| {
+                    ShellError::Redirect {
+                        target: path.clone(),
+                        source,
+                    }
+                })?,
+            };
+            if file.as_raw_fd() == fd {
+                // The file got the very descriptor it is meant for.
+                utils::clear_cloexec(file.into_raw_fd());
+            } else {
+                utils::redirect_fd(&file, fd);
+            }
         }
-        None => {
-            eprintln!("{}: command not found", cmd);
-            None
+    }
+    Ok(saved)
+}
+
+/// Expands the words of a simple command, setting apart its leading
+/// `NAME=value` assignments.
+fn expand_simple_command(
+    cmd: &str,
+    args: &[String],
+    state: &mut ShellState,
+) -> Result<(EnvVars, Vec<String>), ShellError> {
+    state.substitution_status = None;
+    let mut words = std::iter::once(cmd).chain(args.iter().map(String::as_str));
+    let mut assignments = vec![];
+    let mut fields = vec![];
+
+    for word in words.by_ref() {
+        if !expand::is_assignment(word) {
+            fields.extend(expand::expand_word(word, state)?);
+            break;
+        }
+        let assignment = expand::expand_to_string(word, state)?;
+        if let Some((name, value)) = assignment.split_once('=') {
+            assignments.push((name.to_string(), value.to_string()));
+        }
+    }
+    // `local` and `export` take assignments, which are not split.
+    let declaration = fields
+        .first()
+        .is_some_and(|cmd| cmd == "local" || cmd == "export");
+    for word in words {
+        if declaration && expand::is_assignment(word) {
+            fields.push(expand::expand_to_string(word, state)?);
+        } else {
+            fields.extend(expand::expand_word(word, state)?);
         }
     }
+    Ok((assignments, fields))
 }
 
-fn execute_pipeline(programs: Vec<Box<Command>>) -> Option<Output> {
-    if programs.is_empty() {
-        return None;
+fn execute_simple_command(
+    assignments: EnvVars,
+    mut fields: Vec<String>,
+    state: &mut ShellState,
+) -> i32 {
+    if fields.is_empty() {
+        for (name, value) in assignments {
+            state.set_var(&name, &value);
+        }
+        return state.substitution_status.unwrap_or(0);
     }
 
-    // Validate all commands first
-    let executables: Vec<(String, Vec<String>)> = programs
-        .iter()
-        .filter_map(|program| {
-            let (cmd, args) = program.get_cmd_args();
-
-            // Check for builtins
-            if cmd == "type" {
-                let output = args.join("");
-                eprintln!("{} is a shell builtin", output);
-                return None;
-            }
+    let cmd = fields.remove(0);
+    if state.builtins.contains(&cmd) {
+        return with_temporary_vars(&assignments, state, |state| {
+            let mut io = BuiltinIo::standard();
+            commands::run_builtin(&cmd, &fields, &assignments, state, &mut io).unwrap_or(0)
+        });
+    }
+    execute_program(&cmd, fields, &assignments, state)
+}
 
-            // Find executable
-            match utils::find_executable_on_path(&cmd) {
-                Some(path) => {
-                    let executable = path.file_name().unwrap().to_str().unwrap().to_string();
-                    Some((executable, args))
-                }
-                None => {
-                    eprintln!("{}: not found", cmd);
-                    None
-                }
-            }
+/// Runs a builtin with the assignments preceding it, as in `IFS=: read`,
+/// in effect for its duration only.
+fn with_temporary_vars<F>(assignments: &EnvVars, state: &mut ShellState, run: F) -> i32
+where
+    F: FnOnce(&mut ShellState) -> i32,
+{
+    let saved: Vec<(&String, Option<String>)> = assignments
+        .iter()
+        .map(|(name, value)| {
+            let previous = state.get_var(name).map(String::from);
+            state.set_var(name, value);
+            (name, previous)
         })
         .collect();
 
-    if executables.len() != programs.len() {
-        // One or more commands failed to resolve
-        return None;
+    let status = run(state);
+
+    for (name, previous) in saved.into_iter().rev() {
+        match previous {
+            Some(value) => state.set_var(name, &value),
+            None => state.unset_var(name),
+        }
     }
+    status
+}
 
-    // Execute the pipeline
-    if executables.len() == 1 {
-        // Single command, just execute it
-        let (exe, args) = &executables[0];
-        let args_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-        return Some(utils::run_cmd(exe, &args_refs));
+/// Runs the body of the first branch whose condition succeeds. Without
+/// any such branch nor `else`, the status is 0.
+fn execute_if(
+    branches: &[(Vec<Command>, Vec<Command>)],
+    else_body: Option<&[Command]>,
+    state: &mut ShellState,
+) -> i32 {
+    for (condition, body) in branches {
+        if execute_list(condition, state) == 0 {
+            return execute_list(body, state);
+        }
+    }
+    match else_body {
+        Some(body) => execute_list(body, state),
+        None => 0,
     }
+}
 
-    // Spawn all processes in the pipeline
-    let mut children = Vec::new();
-    let mut previous_stdout: Option<std::process::ChildStdout> = None;
+/// Runs the body of the first item with a pattern matching `word`, then
+/// continues according to the item's terminator.
+fn execute_case(word: &str, items: &[CaseItem], state: &mut ShellState) -> i32 {
+    let word = match expand::expand_to_string(word, state) {
+        Ok(word) => word,
+        Err(error) => return report(error),
+    };
+    let mut status = 0;
+    let mut fall_through = false;
 
-    for (i, (exe, args)) in executables.iter().enumerate() {
-        let is_last = i == executables.len() - 1;
-        let args_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    for item in items {
+        let mut matched = fall_through;
+        for pattern in &item.patterns {
+            if matched {
+                break;
+            }
+            match expand::expand_pattern(pattern, state) {
+                Ok(pattern) => matched = utils::pattern_matches(&pattern, &word),
+                Err(error) => return report(error),
+            }
+        }
+        if !matched {
+            continue;
+        }
 
-        let stdin = if let Some(prev_out) = previous_stdout.take() {
-            Stdio::from(prev_out)
-        } else {
-            Stdio::inherit()
-        };
+        status = execute_list(&item.body, state);
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => fall_through = true,
+            CaseTerminator::Continue => fall_through = false,
+        }
+    }
+    status
+}
 
-        let mut child = ProcessCommand::new(exe)
-            .args(&args_refs)
-            .stdin(stdin)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .unwrap();
+/// Whether a loop should go on after running its body once, consuming the
+/// `break`/`continue` meant for it.
+fn continue_loop(state: &mut ShellState) -> bool {
+    match state.flow_control {
+        None | Some(FlowControl::Continue(1)) => {
+            state.flow_control = None;
+            true
+        }
+        Some(FlowControl::Break(1)) => {
+            state.flow_control = None;
+            false
+        }
+        // Leave this loop and let the enclosing one see the rest.
+        Some(FlowControl::Break(n)) => {
+            state.flow_control = Some(FlowControl::Break(n - 1));
+            false
+        }
+        Some(FlowControl::Continue(n)) => {
+            state.flow_control = Some(FlowControl::Continue(n - 1));
+            false
+        }
+        Some(FlowControl::Return(_) | FlowControl::Exit(_)) => false,
+    }
+}
+
+/// Runs `body` in a nested loop, returning whether to keep iterating.
+fn run_loop_body(body: &[Command], status: &mut i32, state: &mut ShellState) -> bool {
+    state.loop_depth += 1;
+    *status = execute_list(body, state);
+    state.loop_depth -= 1;
+    continue_loop(state)
+}
 
-        // Take stdout for next process BEFORE pushing to children
-        if !is_last {
-            previous_stdout = child.stdout.take();
+/// `while` (`until` when `expected` is false) loops. The status is that
+/// of the last body run, or 0 when the body never ran.
+fn execute_while(
+    condition: &[Command],
+    body: &[Command],
+    expected: bool,
+    state: &mut ShellState,
+) -> i32 {
+    let mut status = 0;
+    loop {
+        state.loop_depth += 1;
+        let succeeded = execute_list(condition, state) == 0;
+        state.loop_depth -= 1;
+        if !continue_loop(state) || succeeded != expected {
+            break;
+        }
+        if !run_loop_body(body, &mut status, state) {
+            break;
         }
+    }
+    status
+}
 
-        children.push(child);
+fn execute_for(
+    variable: &str,
+    items: Vec<String>,
+    body: &[Command],
+    state: &mut ShellState,
+) -> i32 {
+    if !expand::is_valid_name(variable) {
+        return report(ShellError::InvalidName(variable.to_string()));
+    }
+
+    let mut status = 0;
+    for item in items {
+        state.set_var(variable, &item);
+        if !run_loop_body(body, &mut status, state) {
+            break;
+        }
     }
+    status
+}
+
+/// `for ((init; condition; step))`, where an empty condition is true.
+fn execute_arithmetic_for(
+    [init, condition, step]: [&String; 3],
+    body: &[Command],
+    state: &mut ShellState,
+) -> i32 {
+    let evaluate = |expression: &str, state: &mut ShellState| match expression {
+        "" => Ok(1),
+        expression => evaluate_arithmetic(expression, state),
+    };
 
-    // Now all processes are spawned and connected via pipes
-    // Get the last process and start reading its output
-    let mut last_child = children.pop().unwrap();
+    let mut status = 0;
+    if let Err(error) = evaluate(init, state) {
+        return report(error);
+    }
+    loop {
+        match evaluate(condition, state) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) => return report(error),
+        }
+        if !run_loop_body(body, &mut status, state) {
+            break;
+        }
+        if let Err(error) = evaluate(step, state) {
+            return report(error);
+        }
+    }
+    status
+}
 
-    // DON'T take stdout/stderr until we're ready to read
-    // The pipe connections are already established in the spawn
-    let stdout = last_child.stdout.take().unwrap();
-    let stderr = last_child.stderr.take().unwrap();
+fn execute_arithmetic(expression: &str, state: &mut ShellState) -> i32 {
+    match evaluate_arithmetic(expression, state) {
+        Ok(0) => 1,
+        Ok(_) => 0,
+        Err(error) => report(error),
+    }
+}
 
-    // Stream stdout in real-time in a separate thread
-    let stdout_handle = thread::spawn(move || {
-        let reader = BufReader::new(stdout);
-        let mut stdout_data = Vec::new();
+/// Evaluates the expression of `((...))`, or one of the three of
+/// `for ((...))`, whose errors are reported as coming from `((`.
+fn evaluate_arithmetic(expression: &str, state: &mut ShellState) -> Result<i64, ShellError> {
+    let expression = expand::expand_arithmetic(expression, state)?;
+    arith::evaluate(&expression, state).map_err(|message| ShellError::builtin("((", message))
+}
 
-        for line in reader.lines() {
-            match line {
-                Ok(line) => {
-                    println!("{}", line);
-                    stdout_data.extend_from_slice(line.as_bytes());
-                    stdout_data.push(b'\n');
-                }
-                Err(_) => break,
-            }
+fn execute_conditional(words: &[String], state: &mut ShellState) -> i32 {
+    match conditional::evaluate(words, state) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(message) => report(ShellError::Builtin {
+            builtin: String::from("[["),
+            message,
+            status: 2,
+        }),
+    }
+}
+
+/// The environment of an external program: exported variables, overridden
+/// by the assignments prefixing the command.
+fn program_env(assignments: &[(String, String)], state: &ShellState) -> EnvVars {
+    let mut env = state.exported_vars();
+    env.extend(assignments.iter().cloned());
+    env
+}
+
+pub fn resolve_program(cmd: &str, state: &ShellState) -> Option<PathBuf> {
+    if cmd.contains('/') {
+        Some(PathBuf::from(cmd))
+    } else {
+        utils::find_executable_in(cmd, state.get_var("PATH").unwrap_or(""))
+    }
+}
+
+fn execute_program(
+    cmd: &str,
+    args: Vec<String>,
+    assignments: &[(String, String)],
+    state: &mut ShellState,
+) -> i32 {
+    if let Some(Command::FunctionDef {
+        body, redirects, ..
+    }) = state.functions.get(cmd).cloned()
+    {
+        return call_function(&body, &redirects, args, assignments, state);
+    }
+
+    let Some(program_path) = resolve_program(cmd, state) else {
+        return report(ShellError::CommandNotFound(cmd.to_string()));
+    };
+    let program_args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let env = program_env(assignments, state);
+
+    match utils::run_cmd(cmd, &program_path, &program_args, &env) {
+        Ok(status) => status,
+        Err(ShellError::Spawn { source, .. }) if source.raw_os_error() == Some(libc::ENOEXEC) => {
+            let child_state = &mut *state;
+            let pid = utils::fork_with(|| {
+                run_file_as_script(&program_path, args, assignments, child_state)
+            });
+            pid.map_or(1, utils::wait_pid)
         }
-        stdout_data
-    });
+        Err(error) => report(error),
+    }
+}
+
+/// Replaces the shell with a program, keeping its pid, as `exec` does.
+/// Only returns, with a status, when the program cannot be run.
+pub fn exec_program(cmd: &str, args: &[String], state: &mut ShellState) -> i32 {
+    let Some(program_path) = resolve_program(cmd, state) else {
+        return report(ShellError::builtin("exec", format!("{cmd}: not found")).with_status(127));
+    };
 
-    // Stream stderr in real-time in a separate thread
-    let stderr_handle = thread::spawn(move || {
-        let reader = BufReader::new(stderr);
-        let mut stderr_data = Vec::new();
+    let _ = io::stdout().flush();
+    let error = ProcessCommand::new(&program_path)
+        .arg0(cmd)
+        .args(args)
+        .env_clear()
+        .envs(state.exported_vars())
+        .exec();
 
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                // eprintln!("{}", line);
-                stderr_data.extend_from_slice(line.as_bytes());
-                stderr_data.push(b'\n');
-            }
+    if error.raw_os_error() == Some(libc::ENOEXEC) {
+        let status = run_file_as_script(&program_path, args.to_vec(), &[], state);
+        std::process::exit(status);
+    }
+    report(ShellError::Spawn {
+        cmd: format!("exec: {cmd}"),
+        source: error,
+    })
+}
+
+/// A file the kernel cannot execute, lacking a `#!` line, is run as a
+/// shell script, as POSIX shells do. Meant for a forked copy of the shell.
+fn run_file_as_script(
+    path: &Path,
+    args: Vec<String>,
+    assignments: &[(String, String)],
+    state: &mut ShellState,
+) -> i32 {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(source) => {
+            return report(ShellError::Spawn {
+                cmd: path.display().to_string(),
+                source,
+            });
         }
-        stderr_data
+    };
+
+    state.reset_for_script();
+    state.script_name = path.to_string_lossy().into_owned();
+    state.positional = args;
+    for (name, value) in assignments {
+        state.set_var(name, value);
+        state.export_var(name);
+    }
+    run_script(&script, state)
+}
+
+/// Runs a function body with `args` as positional parameters, under the
+/// redirects of its definition. Variables assigned in front of the call
+/// only last for its duration.
+fn call_function(
+    body: &Command,
+    redirects: &[Command],
+    args: Vec<String>,
+    assignments: &[(String, String)],
+    state: &mut ShellState,
+) -> i32 {
+    let positional = std::mem::replace(&mut state.positional, args);
+    let loop_depth = std::mem::take(&mut state.loop_depth);
+    state.push_scope();
+    for (name, value) in assignments {
+        state.make_local(name);
+        state.set_var(name, value);
+    }
+
+    let mut status = with_redirects(redirects, state, |state| {
+        execute_list(std::slice::from_ref(body), state)
     });
+    if let Some(FlowControl::Return(returned)) = state.flow_control {
+        state.flow_control = None;
+        status = returned;
+    }
+
+    state.pop_scope();
+    state.loop_depth = loop_depth;
+    state.positional = positional;
+    status
+}
+
+/// A stage of a pipeline: an expanded simple command, or a compound
+/// command run by a forked copy of the shell.
+enum Stage<'a> {
+    Simple(EnvVars, Vec<String>),
+    Compound(&'a Command),
+}
 
-    // Collect output from threads (these will block until pipes close)
-    let stdout_data = stdout_handle.join().unwrap();
-    let stderr_data = stderr_handle.join().unwrap();
+/// A running pipeline stage.
+enum Process {
+    Child(std::process::Child),
+    Forked(libc::pid_t),
+    // A last stage already run by the shell itself.
+    Finished(i32),
+}
 
-    // NOW wait for last child
-    let status = last_child.wait().unwrap();
+impl Process {
+    fn wait(self) -> i32 {
+        match self {
+            Process::Finished(status) => status,
+            Process::Child(mut child) => child.wait().map(utils::status_code).unwrap_or(1),
+            Process::Forked(pid) => utils::wait_pid(pid),
+        }
+    }
+}
 
-    // Wait for remaining children
-    for mut child in children.into_iter().rev() {
-        let _ = child.wait();
+/// Runs `body` in a forked copy of the shell, so that it cannot change
+/// the variables, functions or working directory of the shell itself.
+fn execute_subshell(body: &[Command], state: &mut ShellState) -> i32 {
+    let child_state = &mut *state;
+    match utils::fork_with(|| execute_list(body, child_state)) {
+        Some(pid) => utils::wait_pid(pid),
+        None => 1,
     }
+}
 
-    Some(Output {
-        status,
-        stdout: stdout_data,
-        stderr: stderr_data,
-    })
+/// Whether a simple command has to run within the shell, and therefore
+/// in a forked copy of it when it is a pipeline stage.
+fn runs_in_shell(cmd: &str, state: &ShellState) -> bool {
+    state.builtins.contains(cmd) || state.functions.contains_key(cmd)
+}
+
+/// Whether the last stage of a pipeline runs in the shell itself rather
+/// than a forked copy, as in ksh, so that `cmd | read var` sets `var`.
+fn runs_as_last_stage(fields: &[String]) -> bool {
+    fields.first().is_some_and(|cmd| cmd == "read")
+}
+
+fn execute_pipeline(stages: Vec<Stage>, state: &mut ShellState) -> i32 {
+    if stages.is_empty() {
+        return 0;
+    }
+
+    // Validate all commands first
+    let mut resolved = true;
+    for stage in &stages {
+        let Stage::Simple(_, fields) = stage else {
+            continue;
+        };
+        let Some(cmd) = fields.first() else {
+            continue;
+        };
+
+        if !runs_in_shell(cmd, state) && resolve_program(cmd, state).is_none() {
+            report(ShellError::CommandNotFound(cmd.clone()));
+            resolved = false;
+        }
+    }
+
+    if !resolved {
+        // One or more commands failed to resolve
+        return 127;
+    }
+
+    // Start all processes in the pipeline
+    let _ = io::stdout().flush();
+    let mut processes = Vec::new();
+    let mut previous_stdout: Option<File> = None;
+    let last = stages.len() - 1;
+
+    for (i, stage) in stages.into_iter().enumerate() {
+        let stdin = previous_stdout.take();
+        let (next_stdin, stdout) = if i == last {
+            (None, None)
+        } else {
+            match utils::pipe() {
+                Ok((reader, writer)) => (Some(reader), Some(writer)),
+                Err(error) => {
+                    report(ShellError::io("pipe", error));
+                    break;
+                }
+            }
+        };
+        previous_stdout = next_stdin;
+
+        let process = match stage {
+            Stage::Simple(assignments, fields) if i == last && runs_as_last_stage(&fields) => {
+                run_last_stage(stdin, state, |state| {
+                    execute_simple_command(assignments, fields, state)
+                })
+            }
+            Stage::Simple(assignments, mut fields) if !fields.is_empty() => {
+                if runs_in_shell(&fields[0], state) {
+                    fork_stage(stdin, stdout, state, |state| {
+                        execute_simple_command(assignments, fields, state)
+                    })
+                } else {
+                    let cmd = fields.remove(0);
+                    spawn_stage(&cmd, fields, &assignments, stdin, stdout, state)
+                }
+            }
+            Stage::Simple(assignments, fields) => fork_stage(stdin, stdout, state, |state| {
+                execute_simple_command(assignments, fields, state)
+            }),
+            Stage::Compound(command) => fork_stage(stdin, stdout, state, |state| {
+                execute_with_redirects(Some(command), &[], state)
+            }),
+        };
+        processes.push((i, process));
+    }
+
+    // The last process' status is the status of the whole pipeline
+    let mut status = 127;
+    for (i, process) in processes {
+        let process_status = process.map(Process::wait).unwrap_or(127);
+        if i == last {
+            status = process_status;
+        }
+    }
+    status
 }
 
-fn handle_redirect(
-    output: Output,
-    path: String,
-    redirect_mode: RedirectMode,
-    write_mode: WriteFileMode,
-) {
-    let clean_stdout = utils::strip_bytes(output.stdout);
-    let clean_stderr = utils::strip_bytes(output.stderr);
+fn spawn_stage(
+    cmd: &str,
+    args: Vec<String>,
+    assignments: &[(String, String)],
+    stdin: Option<File>,
+    stdout: Option<File>,
+    state: &mut ShellState,
+) -> Option<Process> {
+    let path = resolve_program(cmd, state)?;
+    let env = program_env(assignments, state);
+    // Kept in case the program turns out to be a script to run ourselves.
+    let stdin_copy = stdin.as_ref().and_then(|file| file.try_clone().ok());
+    let stdout_copy = stdout.as_ref().and_then(|file| file.try_clone().ok());
 
-    let to_file = match redirect_mode {
-        RedirectMode::StdErr => {
-            io::stdout().write_all(clean_stdout.as_slice()).unwrap();
-            clean_stderr
+    let spawned = ProcessCommand::new(&path)
+        .arg0(cmd)
+        .args(&args)
+        .env_clear()
+        .envs(env)
+        .stdin(stdin.map_or_else(Stdio::inherit, Stdio::from))
+        .stdout(stdout.map_or_else(Stdio::inherit, Stdio::from))
+        .spawn();
+
+    match spawned {
+        Ok(child) => Some(Process::Child(child)),
+        Err(error) if error.raw_os_error() == Some(libc::ENOEXEC) => {
+            fork_stage(stdin_copy, stdout_copy, state, |state| {
+                run_file_as_script(&path, args, assignments, state)
+            })
         }
-        RedirectMode::StdOut => {
-            io::stderr().write_all(clean_stderr.as_slice()).unwrap();
-            clean_stdout
+        Err(source) => {
+            report(ShellError::Spawn {
+                cmd: cmd.to_string(),
+                source,
+            });
+            None
         }
+    }
+}
+
+/// Runs the last stage of a pipeline in the shell, reading from `stdin`
+/// in place of the shell's own standard input for the time being.
+fn run_last_stage<F>(stdin: Option<File>, state: &mut ShellState, run: F) -> Option<Process>
+where
+    F: FnOnce(&mut ShellState) -> i32,
+{
+    let Some(stdin) = stdin else {
+        return Some(Process::Finished(run(state)));
     };
+    let saved = match utils::save_fd(libc::STDIN_FILENO) {
+        Ok(saved) => saved,
+        Err(error) => {
+            report(ShellError::io("stdin", error));
+            return None;
+        }
+    };
+    utils::redirect_fd(&stdin, libc::STDIN_FILENO);
+    // Drop our copy of the pipe, so the shell reads end of input once the
+    // writers are done.
+    drop(stdin);
+
+    let status = run(state);
 
-    utils::write_to_file(&to_file, path, write_mode);
+    match saved {
+        Some(saved) => utils::redirect_fd(&saved, libc::STDIN_FILENO),
+        None => utils::close_fd(libc::STDIN_FILENO),
+    }
+    Some(Process::Finished(status))
 }
 
-fn write_output(stdout: &[u8]) {
-    let clean_out = utils::strip_bytes(stdout.to_vec());
-    io::stdout().write_all(clean_out.as_slice()).unwrap();
+fn fork_stage<F>(
+    stdin: Option<File>,
+    stdout: Option<File>,
+    state: &mut ShellState,
+    run: F,
+) -> Option<Process>
+where
+    F: FnOnce(&mut ShellState) -> i32,
+{
+    let child_state = &mut *state;
+    let pid = utils::fork_with(move || {
+        if let Some(stdin) = stdin {
+            utils::redirect_fd(&stdin, libc::STDIN_FILENO);
+        }
+        if let Some(stdout) = stdout {
+            utils::redirect_fd(&stdout, libc::STDOUT_FILENO);
+        }
+        run(child_state)
+    })?;
+    Some(Process::Forked(pid))
 }
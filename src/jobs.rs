@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io::{self, Write};
+
+use crate::enums::Command;
+use crate::interpret;
+use crate::state::ShellState;
+use crate::utils;
+
+/// A command run in the background with `&`, kept in the job table until
+/// it is waited for or reported done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    // `%1` refers to the job numbered 1.
+    pub id: usize,
+    pub pid: libc::pid_t,
+    // The command as written, without the `&`.
+    pub command: String,
+    // The exit status, once the job is done.
+    pub status: Option<i32>,
+}
+
+impl Job {
+    /// The job as `jobs` lists it, with `marker` telling the current job
+    /// (`+`) and the previous one (`-`).
+    fn describe(&self, marker: char) -> String {
+        let (state, suffix) = match self.status {
+            None => (String::from("Running"), " &"),
+            Some(0) => (String::from("Done"), ""),
+            Some(status) => (format!("Exit {status}"), ""),
+        };
+        format!("[{}]{marker}  {state:<24}{}{suffix}", self.id, self.command)
+    }
+}
+
+/// Starts `body` in a forked copy of the shell, reading from `/dev/null`,
+/// and adds it to the job table. Returns the status of `&` itself.
+pub fn start(body: &[Command], command: &str, state: &mut ShellState) -> i32 {
+    let _ = io::stdout().flush();
+    let commands = body.to_vec();
+    let child_state = &mut *state;
+
+    let pid = utils::fork_with(move || {
+        if let Ok(null) = File::open("/dev/null") {
+            utils::redirect_fd(&null, libc::STDIN_FILENO);
+        }
+        child_state.jobs.clear();
+        interpret::interpret_command(commands, child_state)
+    });
+    let Some(pid) = pid else {
+        return 1;
+    };
+
+    let id = state.jobs.last().map_or(1, |job| job.id + 1);
+    state.jobs.push(Job {
+        id,
+        pid,
+        command: command.to_string(),
+        status: None,
+    });
+    state.last_background_pid = Some(pid);
+    if state.options.interactive {
+        eprintln!("[{id}] {pid}");
+    }
+    0
+}
+
+/// Records the status of the jobs that finished since last checked,
+/// without waiting for the others.
+pub fn update(state: &mut ShellState) {
+    for job in state.jobs.iter_mut().filter(|job| job.status.is_none()) {
+        job.status = utils::try_wait_pid(job.pid);
+    }
+}
+
+/// The lines `jobs` shows, or the process ids with `pids_only`. Finished
+/// jobs are listed once, then forgotten.
+pub fn list(state: &mut ShellState, pids_only: bool) -> String {
+    update(state);
+    let output = describe_jobs(state, |_| true, pids_only);
+    state.jobs.retain(|job| job.status.is_none());
+    output
+}
+
+/// The lines telling which jobs finished, as an interactive shell shows
+/// them before its prompt, after which they are forgotten.
+pub fn take_done(state: &mut ShellState) -> String {
+    update(state);
+    let output = describe_jobs(state, |job| job.status.is_some(), false);
+    state.jobs.retain(|job| job.status.is_none());
+    output
+}
+
+fn describe_jobs(state: &ShellState, shown: impl Fn(&Job) -> bool, pids_only: bool) -> String {
+    let count = state.jobs.len();
+    let mut output = String::new();
+    for (idx, job) in state.jobs.iter().enumerate().filter(|(_, job)| shown(job)) {
+        let line = if pids_only {
+            job.pid.to_string()
+        } else {
+            let marker = match count - idx {
+                1 => '+',
+                2 => '-',
+                _ => ' ',
+            };
+            job.describe(marker)
+        };
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+/// The position in the job table of the job `spec` names: `%n` for the
+/// job numbered n, `%%` or `%+` for the current one, or a process id.
+pub fn find(state: &ShellState, spec: &str) -> Option<usize> {
+    match spec.strip_prefix('%') {
+        Some("%" | "+") => state.jobs.len().checked_sub(1),
+        Some(id) => state.jobs.iter().position(|job| id.parse() == Ok(job.id)),
+        None => state
+            .jobs
+            .iter()
+            .position(|job| spec.parse() == Ok(job.pid)),
+    }
+}
+
+/// Waits for the job at `idx` in the job table to finish and forgets it,
+/// returning its status.
+pub fn wait(state: &mut ShellState, idx: usize) -> i32 {
+    let job = state.jobs.remove(idx);
+    job.status.unwrap_or_else(|| utils::wait_pid(job.pid))
+}
+
+/// Waits for every background job and empties the job table.
+pub fn wait_all(state: &mut ShellState) {
+    while !state.jobs.is_empty() {
+        wait(state, 0);
+    }
+}
@@ -0,0 +1,453 @@
+use std::ops::Range;
+
+use crate::error::{SyntaxError, SyntaxErrorKind};
+use crate::parse;
+
+/// A token of shell input, with the byte offsets it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// A word as written, quotes and substitutions included.
+    Word(String),
+    /// A reserved word such as `if` or `{`, only recognized where a
+    /// command starts.
+    Reserved(&'static str),
+    /// A control or redirection operator, such as `;;` or `>>`.
+    Operator(&'static str),
+    /// The digits of `2>`, naming the descriptor a redirect applies to.
+    IoNumber(i32),
+    Newline,
+    /// The expression of `((...))`.
+    Arithmetic(String),
+    /// The inside of `[[ ... ]]`, in which `&&`, `<` and the like are
+    /// operands rather than operators.
+    Conditional(String),
+    /// The delimiter word of `<<` or `<<-` as written, with the body read
+    /// from the lines that follow.
+    HereDoc {
+        delimiter: String,
+        body: String,
+    },
+    Eof,
+}
+
+impl Token {
+    /// The token as shown in syntax errors.
+    pub fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word)
+            | TokenKind::Arithmetic(word)
+            | TokenKind::Conditional(word)
+            | TokenKind::HereDoc {
+                delimiter: word, ..
+            } => word.clone(),
+            TokenKind::Reserved(word) | TokenKind::Operator(word) => word.to_string(),
+            TokenKind::IoNumber(fd) => fd.to_string(),
+            TokenKind::Newline | TokenKind::Eof => String::from("newline"),
+        }
+    }
+}
+
+const OPERATORS: [&str; 19] = [
+    ";;&", "<<-", ";;", ";&", "&&", "||", ">>", ">&", ">|", "<<", "<&", "<>", ";", "&", "|", "(",
+    ")", "<", ">",
+];
+
+const REDIRECT_OPERATORS: [&str; 9] = [">>", ">&", ">|", "<<", "<&", "<>", "<<-", "<", ">"];
+
+const RESERVED_WORDS: [&str; 15] = [
+    "if", "then", "else", "elif", "fi", "do", "done", "case", "esac", "while", "until", "for",
+    "function", "{", "}",
+];
+
+/// Reserved words after which another command starts, as `then` in
+/// `if a; then b; fi`.
+const OPENING_WORDS: [&str; 8] = ["if", "then", "else", "elif", "do", "while", "until", "{"];
+
+/// Splits shell input into tokens, one at a time as the parser asks for
+/// them. Whether a word is a reserved word, or `((` and `[[` open an
+/// arithmetic or conditional command, depends on whether a command starts
+/// there, which the lexer follows from the tokens it returned.
+pub struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+    command_start: bool,
+    // `for ((...))` also reads an arithmetic expression.
+    after_for: bool,
+    // The input ends with an escaped newline.
+    continued: bool,
+    // The last token was `<<` (false) or `<<-` (true), so a delimiter word
+    // comes next.
+    here_doc: Option<bool>,
+    // Where the here-document bodies read for the current line end, and
+    // the next line starts.
+    here_doc_end: Option<usize>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Lexer {
+            input,
+            pos: 0,
+            command_start: true,
+            after_for: false,
+            continued: false,
+            here_doc: None,
+            here_doc_end: None,
+        }
+    }
+
+    /// Has the next word read where a command starts, after a reserved
+    /// word the parser accepted in the form of a plain word, as `do` in
+    /// `for x do`.
+    pub fn start_command(&mut self) {
+        self.command_start = true;
+    }
+
+    /// Whether the input read so far ends with an escaped newline, which
+    /// joins it to a line still to come.
+    pub fn continues_line(&self) -> bool {
+        self.continued
+    }
+
+    /// The input between the given byte offsets.
+    pub fn source(&self, span: Range<usize>) -> &'a str {
+        &self.input[span]
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, SyntaxError> {
+        self.skip_blanks();
+        let start = self.pos;
+        let kind = self.read_token()?;
+
+        let after_for = matches!(kind, TokenKind::Reserved("for"));
+        self.command_start = match &kind {
+            TokenKind::Newline | TokenKind::Arithmetic(_) => true,
+            TokenKind::Operator(op) => !REDIRECT_OPERATORS.contains(op),
+            TokenKind::Reserved(word) => OPENING_WORDS.contains(word),
+            _ => false,
+        };
+        self.after_for = after_for;
+        self.here_doc = match kind {
+            TokenKind::Operator("<<") => Some(false),
+            TokenKind::Operator("<<-") => Some(true),
+            _ => None,
+        };
+        Ok(Token {
+            kind,
+            span: start..self.pos,
+        })
+    }
+
+    fn read_token(&mut self) -> Result<TokenKind, SyntaxError> {
+        let rest = &self.input[self.pos..];
+        let Some(c) = rest.chars().next() else {
+            return Ok(TokenKind::Eof);
+        };
+
+        if c == '\n' {
+            // The line is followed by the bodies of its here-documents.
+            self.pos = self.here_doc_end.take().unwrap_or(self.pos + 1);
+            return Ok(TokenKind::Newline);
+        }
+        if (self.command_start || self.after_for)
+            && rest.starts_with("((")
+            && let Some(end) = self.arithmetic_end()?
+        {
+            let expression = self.input[self.pos + 2..end - 2].to_string();
+            self.pos = end;
+            return Ok(TokenKind::Arithmetic(expression));
+        }
+        if self.command_start && (rest.starts_with("[[ ") || rest.starts_with("[[\t")) {
+            return self.read_conditional();
+        }
+        // `<(cmd)` and `>(cmd)` are process substitutions, not redirects.
+        let substitution = matches!(c, '<' | '>') && rest[1..].starts_with('(');
+        if !substitution && let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            self.pos += op.len();
+            return Ok(TokenKind::Operator(op));
+        }
+
+        let start = self.pos;
+        let word = self.read_word()?;
+        if let Some(strip_tabs) = self.here_doc {
+            let body = self.read_here_doc(start, &word, strip_tabs)?;
+            return Ok(TokenKind::HereDoc {
+                delimiter: word,
+                body,
+            });
+        }
+        let next = self.input[self.pos..].chars().next();
+        if word.bytes().all(|b| b.is_ascii_digit())
+            && matches!(next, Some('<' | '>'))
+            && let Ok(fd) = word.parse()
+        {
+            return Ok(TokenKind::IoNumber(fd));
+        }
+        if self.command_start
+            && let Some(reserved) = RESERVED_WORDS.iter().find(|reserved| **reserved == word)
+        {
+            return Ok(TokenKind::Reserved(reserved));
+        }
+        Ok(TokenKind::Word(word))
+    }
+
+    /// Reads the body of the here-document whose delimiter `word` starts
+    /// at `start`: the lines following the current one, or the bodies of
+    /// the earlier here-documents on it, up to a line that is the unquoted
+    /// delimiter. `<<-` strips the leading tabs of each line.
+    fn read_here_doc(
+        &mut self,
+        start: usize,
+        word: &str,
+        strip_tabs: bool,
+    ) -> Result<String, SyntaxError> {
+        let delimiter = parse::remove_quotes(word);
+        let span = start..self.pos;
+        let unterminated = || {
+            SyntaxError::new(
+                SyntaxErrorKind::Unterminated(delimiter.clone()),
+                span.clone(),
+            )
+        };
+
+        let mut pos = match self.here_doc_end {
+            Some(end) => end,
+            None => self.line_end(self.pos)?.ok_or_else(unterminated)? + 1,
+        };
+        let mut body = String::new();
+        while pos < self.input.len() {
+            let end = self.input[pos..]
+                .find('\n')
+                .map_or(self.input.len(), |idx| pos + idx);
+            let mut line = &self.input[pos..end];
+            if strip_tabs {
+                line = line.trim_start_matches('\t');
+            }
+            pos = (end + 1).min(self.input.len());
+            if line == delimiter {
+                self.here_doc_end = Some(pos);
+                return Ok(body);
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        Err(unterminated())
+    }
+
+    /// The newline ending the line `from` is on, past quotes and
+    /// substitutions that span lines, if the input has one.
+    fn line_end(&self, from: usize) -> Result<Option<usize>, SyntaxError> {
+        let mut pos = from;
+        while let Some(c) = self.input[pos..].chars().next() {
+            match c {
+                '\n' => return Ok(Some(pos)),
+                '\\' if self.input[pos + 1..].starts_with('\n') => pos += 2,
+                '#' if self.input[..pos].ends_with([' ', '\t']) => {
+                    pos += self.input[pos..]
+                        .find('\n')
+                        .unwrap_or(self.input.len() - pos);
+                }
+                _ => pos = self.quoted_end(pos)?,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Skips blanks, escaped newlines, which join lines, and comments up to
+    /// the end of their line.
+    fn skip_blanks(&mut self) {
+        loop {
+            let rest = &self.input[self.pos..];
+            if rest.starts_with([' ', '\t']) {
+                self.pos += 1;
+            } else if rest.starts_with("\\\n") {
+                self.skip_line_continuation();
+            } else if rest.starts_with('#') {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn skip_line_continuation(&mut self) {
+        self.pos += 2;
+        self.continued = self.pos == self.input.len();
+    }
+
+    /// Reads a word up to an unquoted blank or operator. Quotes,
+    /// substitutions and escapes are kept as written, except for escaped
+    /// newlines, which are removed.
+    fn read_word(&mut self) -> Result<String, SyntaxError> {
+        let mut word = String::new();
+
+        while let Some(c) = self.input[self.pos..].chars().next() {
+            let start = self.pos;
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '(' | ')' => break,
+                '<' | '>' if !self.input[start + 1..].starts_with('(') => break,
+                '\\' if self.input[start + 1..].starts_with('\n') => {
+                    self.skip_line_continuation();
+                    continue;
+                }
+                '<' | '>' => self.pos = self.parenthesized_end(start + 1)?,
+                _ => self.pos = self.quoted_end(start)?,
+            }
+            word.push_str(&self.input[start..self.pos]);
+        }
+        Ok(word)
+    }
+
+    /// The end of the quoted part, substitution or single character
+    /// starting at `start`.
+    fn quoted_end(&self, start: usize) -> Result<usize, SyntaxError> {
+        let rest = &self.input[start..];
+        let unterminated = |closing: &str| {
+            let kind = SyntaxErrorKind::Unterminated(closing.to_string());
+            SyntaxError::new(kind, start..start + 1)
+        };
+
+        if rest.starts_with("$(") {
+            return self.parenthesized_end(start + 1);
+        }
+        if rest.starts_with("${") {
+            return self.braced_end(start + 1);
+        }
+        // In `$'...'`, a backslash escapes the quote.
+        if rest.starts_with("$'") {
+            let mut chars = rest.char_indices().skip(2);
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '\'' => return Ok(start + idx + 1),
+                    _ => {}
+                }
+            }
+            return Err(unterminated("'"));
+        }
+        match rest.chars().next() {
+            Some('\'') => rest[1..]
+                .find('\'')
+                .map(|idx| start + idx + 2)
+                .ok_or_else(|| unterminated("'")),
+            Some(quote @ ('"' | '`')) => {
+                let mut pos = start + 1;
+                while let Some(c) = self.input[pos..].chars().next() {
+                    pos = match c {
+                        '\\' => {
+                            pos + 1
+                                + self.input[pos + 1..]
+                                    .chars()
+                                    .next()
+                                    .map_or(0, char::len_utf8)
+                        }
+                        _ if c == quote => return Ok(pos + 1),
+                        '$' if quote == '"' && !self.input[pos..].starts_with("$'") => {
+                            self.quoted_end(pos)?
+                        }
+                        '`' => self.quoted_end(pos)?,
+                        _ => pos + c.len_utf8(),
+                    };
+                }
+                Err(unterminated(&quote.to_string()))
+            }
+            Some('\\') => Ok(start + 1 + rest[1..].chars().next().map_or(0, char::len_utf8)),
+            Some(c) => Ok(start + c.len_utf8()),
+            None => Ok(start),
+        }
+    }
+
+    /// The end of the parentheses opening at `open`, as in `$(...)`. The
+    /// commands inside are parsed right away so that their syntax errors
+    /// point into the whole input; `$((...))` holds an arithmetic
+    /// expression instead.
+    fn parenthesized_end(&self, open: usize) -> Result<usize, SyntaxError> {
+        let end = self.matching_end(open, '(', ')')?;
+        let body = &self.input[open + 1..end - 1];
+        if !(body.starts_with('(') && body.ends_with(')'))
+            && let Err(mut error) = parse::run_parser(body)
+        {
+            error.span = error.span.start + open + 1..error.span.end + open + 1;
+            return Err(error);
+        }
+        Ok(end)
+    }
+
+    fn braced_end(&self, open: usize) -> Result<usize, SyntaxError> {
+        self.matching_end(open, '{', '}')
+    }
+
+    /// The end of the `opening` character at `open` and everything up to
+    /// its unquoted `closing` match.
+    fn matching_end(
+        &self,
+        open: usize,
+        opening: char,
+        closing: char,
+    ) -> Result<usize, SyntaxError> {
+        let mut depth = 0;
+        let mut pos = open;
+        while let Some(c) = self.input[pos..].chars().next() {
+            if c == opening {
+                depth += 1;
+            } else if c == closing {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(pos + 1);
+                }
+            } else if matches!(c, '\'' | '"' | '`' | '\\' | '$') {
+                pos = self.quoted_end(pos)?;
+                continue;
+            }
+            pos += c.len_utf8();
+        }
+        let kind = SyntaxErrorKind::Unterminated(closing.to_string());
+        Err(SyntaxError::new(kind, open..open + 1))
+    }
+
+    /// Where `((...))` starting here ends, unless the parentheses are
+    /// those of nested subshells, as in `((a); b)`.
+    fn arithmetic_end(&self) -> Result<Option<usize>, SyntaxError> {
+        let end = self.matching_end(self.pos, '(', ')')?;
+        let inner_end = self.matching_end(self.pos + 1, '(', ')')?;
+        Ok((inner_end == end - 1).then_some(end))
+    }
+
+    /// Reads `[[ ... ]]` up to a `]]` following a blank.
+    fn read_conditional(&mut self) -> Result<TokenKind, SyntaxError> {
+        let start = self.pos;
+        let mut pos = start + 2;
+        while let Some(c) = self.input[pos..].chars().next() {
+            if matches!(c, ' ' | '\t' | '\n') && self.input[pos + 1..].starts_with("]]") {
+                let inside = self.input[start + 2..pos + 1].to_string();
+                self.pos = pos + 3;
+                return Ok(TokenKind::Conditional(inside));
+            }
+            pos = self.quoted_end(pos)?;
+        }
+        let kind = SyntaxErrorKind::Unterminated(String::from("]]"));
+        Err(SyntaxError::new(kind, start..start + 1))
+    }
+}
+
+/// Splits all of `input` into tokens, ending with [`TokenKind::Eof`].
+pub fn tokenize(input: &str) -> Result<Vec<Token>, SyntaxError> {
+    let mut lexer = Lexer::new(input);
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token()?;
+        let eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
@@ -1,5 +1,15 @@
+pub mod arith;
+pub mod builtins;
 pub mod commands;
+pub mod conditional;
 pub mod enums;
+pub mod error;
+pub mod expand;
 pub mod interpret;
+pub mod jobs;
+pub mod lexer;
 pub mod parse;
+pub mod printf;
+pub mod shell;
+pub mod state;
 pub mod utils;
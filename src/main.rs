@@ -1,25 +1,236 @@
 use std::{
-    env,
-    io::{self},
+    env, fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process,
 };
 
-use codecrafters_shell::{commands::handle_command, enums::WriteFileMode, utils};
+use codecrafters_shell::{
+    enums::WriteFileMode,
+    error::{self, ShellError},
+    expand, interpret, jobs, parse,
+    shell::Shell,
+    state::{FlowControl, ShellState},
+    utils,
+};
+
+/// What the shell was asked to run on its command line.
+enum Invocation {
+    Interactive,
+    // Commands read from a non-interactive standard input.
+    Stdin,
+    Command(String),
+    Script(String),
+}
+
+/// Which startup files to run, as set by the command line, besides the
+/// shell's options.
+#[derive(Default)]
+struct StartupOptions {
+    no_profile: bool,
+    no_rc: bool,
+    rc_file: Option<String>,
+}
+
+/// Parses `[-s] [FILE] args...` and `-c STRING [name] args...`, setting `$0`,
+/// the positional parameters and the shell's options.
+fn parse_args(state: &mut ShellState) -> (Invocation, StartupOptions) {
+    let mut args = env::args().skip(1);
+    let mut invocation = None;
+    let mut read_stdin = false;
+    let mut operands = vec![];
+    let mut options = StartupOptions::default();
+    // `login` runs us as `-name`.
+    state.options.login = env::args().next().is_some_and(|arg0| arg0.starts_with('-'));
+    state.options.posix = shell_name() == "sh";
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-l" | "--login" => state.options.login = true,
+            "-i" => state.options.interactive = true,
+            "--posix" => state.options.posix = true,
+            "--noprofile" => options.no_profile = true,
+            "--norc" => options.no_rc = true,
+            "--rcfile" | "--init-file" => match args.next() {
+                Some(path) => options.rc_file = Some(path),
+                None => {
+                    eprintln!("{}: {arg}: option requires an argument", shell_name());
+                    process::exit(2);
+                }
+            },
+            "-c" => match args.next() {
+                Some(command) => invocation = Some(Invocation::Command(command)),
+                None => {
+                    eprintln!("{}: -c: option requires an argument", shell_name());
+                    process::exit(2);
+                }
+            },
+            "-s" => read_stdin = true,
+            "--" => {
+                operands.extend(args.by_ref());
+            }
+            option if option.starts_with('-') && option.len() > 1 => {
+                eprintln!("{}: {option}: invalid option", shell_name());
+                process::exit(2);
+            }
+            _ => {
+                operands.push(arg);
+                operands.extend(args.by_ref());
+            }
+        }
+    }
+
+    // With `-c`, the first operand names the shell, and with neither `-c`
+    // nor `-s`, it is the script to run.
+    let mut operands = operands.into_iter();
+    let invocation = match invocation {
+        Some(invocation) => {
+            if let Some(name) = operands.next() {
+                state.script_name = name;
+            }
+            invocation
+        }
+        None if read_stdin => Invocation::Stdin,
+        None => match operands.next() {
+            Some(path) => {
+                state.script_name = path.clone();
+                Invocation::Script(path)
+            }
+            None => Invocation::Stdin,
+        },
+    };
+    state.positional = operands.collect();
+
+    let invocation = match invocation {
+        Invocation::Stdin if state.options.interactive || io::stdin().is_terminal() => {
+            Invocation::Interactive
+        }
+        invocation => invocation,
+    };
+    state.options.interactive = matches!(invocation, Invocation::Interactive);
+    (invocation, options)
+}
+
+fn shell_name() -> String {
+    let arg0 = env::args().next().unwrap_or_default();
+    let name = Path::new(&arg0)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or(arg0);
+    name.trim_start_matches('-').to_string()
+}
+
+/// Runs the startup files: the profiles for login shells, then the rc
+/// file (or, in POSIX mode, the file named by `ENV`) for interactive ones.
+fn run_startup_files(options: &StartupOptions, state: &mut ShellState) {
+    let home = state.get_var("HOME").map(PathBuf::from);
+    let mut files = vec![];
+
+    if state.options.login && !options.no_profile {
+        files.push(PathBuf::from("/etc/profile"));
+        files.extend(home.as_ref().map(|home| home.join(".profile")));
+    }
+    if state.options.interactive && state.options.posix {
+        let env_file = state.get_var("ENV").map(String::from).and_then(|env_file| {
+            expand::expand_to_string(&env_file, state)
+                .map_err(interpret::report)
+                .ok()
+        });
+        files.extend(env_file.filter(|path| !path.is_empty()).map(PathBuf::from));
+    } else if state.options.interactive && !options.no_rc {
+        let rc_file = match &options.rc_file {
+            Some(path) => Some(PathBuf::from(path)),
+            None => home.map(|home| home.join(format!(".{}rc", shell_name()))),
+        };
+        files.extend(rc_file);
+    }
+
+    for path in files {
+        match interpret::run_file(&path, state) {
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                let error = error::describe(&error);
+                eprintln!("{}: {}: {error}", shell_name(), path.display());
+            }
+        }
+        if let Some(FlowControl::Exit(status)) = state.flow_control {
+            process::exit(status);
+        }
+    }
+}
 
-fn init() -> (Vec<String>, Option<String>) {
+fn init(state: &mut ShellState) -> Option<String> {
     let history_path = env::var("HISTFILE").ok();
-    let mut history = Vec::new();
+    if state.get_var("PS2").is_none() {
+        state.set_var("PS2", "> ");
+    }
+
+    if let Some(path) = history_path.as_ref() {
+        match utils::fill_history(path, &mut state.history) {
+            Ok(()) => {}
+            // A history file is only created on exit.
+            Err(ShellError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => eprintln!("{}: {error}", shell_name()),
+        }
+    }
 
     history_path
-        .as_ref()
-        .map(|path| utils::fill_history(path, &mut history));
+}
 
-    (history, history_path)
+/// Keeps appending lines to `input` while it ends inside a compound
+/// command such as an unterminated `if`, quotes, a pipeline or after an
+/// escaped newline.
+fn read_continuation_lines<F>(input: &mut String, mut read_line: F)
+where
+    F: FnMut(&mut String) -> bool,
+{
+    while parse::is_incomplete(input) {
+        if !read_line(input) {
+            break;
+        }
+    }
 }
 
-fn main() {
-    let (mut history, maybe_path) = init();
+fn read_stdin_line(input: &mut String) -> bool {
+    match utils::read_line_unbuffered(libc::STDIN_FILENO) {
+        Some(line) => {
+            input.push_str(&line);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Runs the commands of a non-interactive standard input as they come,
+/// without prompting, until a syntax error.
+fn run_stdin(state: &mut ShellState) {
+    let mut input_str = String::new();
+
+    while read_stdin_line(&mut input_str) {
+        read_continuation_lines(&mut input_str, read_stdin_line);
+        match parse::run_parser(&input_str) {
+            Ok(commands) => {
+                interpret::interpret_command(commands, state);
+            }
+            Err(error) => {
+                state.last_status = interpret::report(error.into());
+                break;
+            }
+        }
+        if let Some(FlowControl::Exit(status)) = state.flow_control {
+            process::exit(status);
+        }
+        input_str.clear();
+    }
+}
+
+fn run_interactive(shell: &mut Shell) {
+    let maybe_path = init(shell.state_mut());
+    let read_line = |input: &mut String| matches!(io::stdin().read_line(input), Ok(1..));
 
     loop {
+        eprint!("{}", jobs::take_done(shell.state_mut()));
         eprint!("$ ");
         let mut input_str = String::new();
         match io::stdin().read_line(&mut input_str) {
@@ -28,19 +239,25 @@ fn main() {
                 break;
             }
             Ok(_) => {
-                history.push(input_str.clone().trim().to_string());
-                let cmd_args: Vec<&str> = input_str.split_whitespace().collect();
-                let (cmd, args) = cmd_args.split_first().unwrap();
-                if cmd.eq_ignore_ascii_case("exit") {
-                    match maybe_path {
-                        Some(pathref) => {
-                            utils::dump_history(pathref, &mut history, WriteFileMode::OverWrite)
+                // The lines of a command are all run, and kept in the
+                // history, together.
+                let ps2 = shell.var("PS2").unwrap_or_default().to_string();
+                read_continuation_lines(&mut input_str, |input| {
+                    eprint!("{ps2}");
+                    read_line(input)
+                });
+                shell.run_line(&input_str);
+                if let Some(status) = shell.exit_status() {
+                    if let Some(pathref) = maybe_path {
+                        let history = &mut shell.state_mut().history;
+                        if let Err(error) =
+                            utils::dump_history(pathref, history, WriteFileMode::OverWrite)
+                        {
+                            eprintln!("{}: {error}", shell_name());
                         }
-                        None => {}
                     }
-                    break;
+                    process::exit(status);
                 }
-                handle_command(cmd, args, &input_str, &mut history);
             }
             Err(error) => {
                 eprintln!("Error reading input: {error}");
@@ -49,3 +266,29 @@ fn main() {
         }
     }
 }
+
+fn main() {
+    let mut shell = Shell::new();
+    let (invocation, options) = parse_args(shell.state_mut());
+    run_startup_files(&options, shell.state_mut());
+
+    match invocation {
+        Invocation::Interactive => run_interactive(&mut shell),
+        Invocation::Stdin => run_stdin(shell.state_mut()),
+        Invocation::Command(command) => {
+            shell.run_script(&command);
+        }
+        Invocation::Script(path) => match fs::read_to_string(&path) {
+            Ok(script) => {
+                shell.run_script(&script);
+            }
+            Err(error) => {
+                let error = error::describe(&error);
+                eprintln!("{}: {path}: {error}", shell_name());
+                process::exit(127);
+            }
+        },
+    }
+
+    process::exit(shell.last_status());
+}
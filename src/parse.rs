@@ -1,242 +1,545 @@
-use crate::enums::{Command, RedirectMode, WriteFileMode};
-
-pub fn run_parser(input: &str) -> Vec<Command> {
-    let mut parser = Parser::new();
+use crate::enums::{CaseItem, CaseTerminator, Command, RedirectMode, WriteFileMode};
+use crate::error::{SyntaxError, SyntaxErrorKind};
+use crate::lexer::{Lexer, Token, TokenKind};
+
+/// Parses complete input, such as a command line or a whole script. The
+/// first syntax error found is returned, with offsets into `input`.
+pub fn run_parser(input: &str) -> Result<Vec<Command>, SyntaxError> {
+    Parser::new(input).parse_list(&[])
+}
 
-    for c in input.chars() {
-        parser.handle_char(c);
+/// Whether `input` stops in the middle of a compound command, a pipeline,
+/// an AND-OR list, a here-document or quotes, or right after an escaped
+/// newline, in which case the shell reads more lines before running it.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut parser = Parser::new(input);
+    match parser.parse_list(&[]) {
+        Ok(_) => parser.lexer.continues_line(),
+        Err(error) => error.is_incomplete(),
     }
-
-    parser.finish()
 }
 
-struct Parser {
-    commands: Vec<Command>,
-    current_cmd: Option<Command>,
-    last_char: char,
-    pending_fd_char: Option<char>,
+/// Builds commands from the tokens of the lexer, one grammar rule per
+/// method. Redirects are placed after the command they apply to, as
+/// `RedirectTo` entries of the same list.
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
 }
 
-impl Parser {
-    fn new() -> Self {
+const REDIRECT_OPERATORS: [&str; 9] = [">", ">|", ">>", ">&", "<", "<<", "<<-", "<&", "<>"];
+
+const CASE_TERMINATORS: [&str; 3] = [";;", ";&", ";;&"];
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
         Parser {
-            commands: vec![],
-            current_cmd: None,
-            last_char: ' ',
-            pending_fd_char: None,
+            lexer: Lexer::new(input),
+            peeked: None,
         }
     }
 
-    fn handle_char(&mut self, c: char) {
-        match c {
-            '\'' | '"' | '\n' => {}
-            '>' => self.handle_redirect(),
-            '1' | '2' => self.handle_fd_marker(c),
-            '|' => self.handle_pipe(),
-            ';' => self.handle_semicolon(),
-            ' ' => self.handle_space(),
-            _ => self.handle_regular_char(c),
+    fn peek(&mut self) -> Result<&Token, SyntaxError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next_token()?);
         }
+        Ok(self.peeked.as_ref().unwrap())
+    }
 
-        self.last_char = c;
+    fn next(&mut self) -> Result<Token, SyntaxError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lexer.next_token(),
+        }
     }
 
-    fn handle_redirect(&mut self) {
-        if let Some(fd_char) = self.pending_fd_char.take() {
-            self.finalize_current_command();
-            self.current_cmd = Some(self.create_redirect_command(fd_char));
-        } else {
-            if self.try_convert_to_append() {
-                return;
-            }
+    /// Whether the next token is `text`, as a reserved word, an operator
+    /// or a plain word.
+    fn peek_is(&mut self, text: &str) -> Result<bool, SyntaxError> {
+        Ok(match &self.peek()?.kind {
+            TokenKind::Reserved(word) | TokenKind::Operator(word) => *word == text,
+            TokenKind::Word(word) => word == text,
+            _ => false,
+        })
+    }
 
-            self.finalize_current_command();
-            self.current_cmd = Some(self.create_redirect_command('1'));
+    /// Reads `text`. A reserved word is accepted as a plain word as well,
+    /// as `do` in `for x do`, after which a command starts.
+    fn expect(&mut self, text: &str) -> Result<(), SyntaxError> {
+        if !self.peek_is(text)? {
+            let token = self.next()?;
+            return Err(unexpected(&token));
+        }
+        if let TokenKind::Word(_) = self.next()?.kind {
+            self.lexer.start_command();
         }
+        Ok(())
     }
 
-    fn try_convert_to_append(&mut self) -> bool {
-        if let Some(Command::RedirectTo { write_mode, .. }) = &mut self.current_cmd {
-            if *write_mode == WriteFileMode::OverWrite && self.last_char == '>' {
-                *write_mode = WriteFileMode::Append;
-                return true;
-            }
+    fn skip_newlines(&mut self) -> Result<(), SyntaxError> {
+        while self.peek()?.kind == TokenKind::Newline {
+            self.next()?;
         }
-        false
+        Ok(())
     }
 
-    fn create_redirect_command(&self, fd_char: char) -> Command {
-        Command::RedirectTo {
-            path: String::new(),
-            redirect_mode: if fd_char == '1' {
-                RedirectMode::StdOut
-            } else {
-                RedirectMode::StdErr
-            },
-            write_mode: WriteFileMode::OverWrite,
+    /// Reads AND-OR lists separated by `;`, `&` or newlines up to the end of
+    /// the input or one of the `stops`, which is left to the caller. Those
+    /// followed by `&` run in the background.
+    fn parse_list(&mut self, stops: &[&str]) -> Result<Vec<Command>, SyntaxError> {
+        let mut commands = vec![];
+        loop {
+            self.skip_newlines()?;
+            if self.at_stop(stops)? {
+                return Ok(commands);
+            }
+            let (first, start) = (commands.len(), self.peek()?.span.start);
+            self.parse_and_or(&mut commands)?;
+
+            if self.peek()?.kind == TokenKind::Operator("&") {
+                let end = self.next()?.span.start;
+                let body = commands.split_off(first);
+                let command = self.lexer.source(start..end).trim_end().to_string();
+                commands.push(Command::Background { body, command });
+            } else if matches!(
+                self.peek()?.kind,
+                TokenKind::Operator(";") | TokenKind::Newline
+            ) {
+                self.next()?;
+            } else if !self.at_stop(stops)? {
+                return Err(unexpected(&self.next()?));
+            }
         }
     }
 
-    fn handle_fd_marker(&mut self, c: char) {
-        self.flush_pending_fd_char();
-        self.pending_fd_char = Some(c);
+    fn at_stop(&mut self, stops: &[&str]) -> Result<bool, SyntaxError> {
+        Ok(match &self.peek()?.kind {
+            TokenKind::Eof => true,
+            TokenKind::Reserved(word) | TokenKind::Operator(word) => stops.contains(word),
+            _ => false,
+        })
     }
 
-    fn handle_pipe(&mut self) {
-        self.flush_pending_fd_char();
-
-        if let Some(current) = self.current_cmd.take() {
-            self.current_cmd = match current {
-                // If already a Pipe, add to it
-                Command::Pipe { mut programs } => {
-                    programs.push(Box::new(Command::Program {
-                        cmd: String::new(),
-                        args: vec![],
-                    }));
-                    Some(Command::Pipe { programs })
-                }
-                // Otherwise, create a new Pipe with 2 programs
-                _ => Some(Command::Pipe {
-                    programs: vec![
-                        Box::new(current),
-                        Box::new(Command::Program {
-                            cmd: String::new(),
-                            args: vec![],
-                        }),
-                    ],
-                }),
-            };
+    /// A list that is part of a compound command, which may not be empty.
+    fn parse_body(&mut self, stops: &[&str]) -> Result<Vec<Command>, SyntaxError> {
+        let body = self.parse_list(stops)?;
+        if body.is_empty() {
+            return Err(unexpected(&self.next()?));
         }
+        Ok(body)
     }
 
-    fn handle_semicolon(&mut self) {
-        self.flush_pending_fd_char();
-        self.finalize_current_command();
-        self.current_cmd = Some(Command::Program {
-            cmd: String::new(),
-            args: vec![],
-        });
+    /// Reads pipelines joined by `&&` or `||`, which may be followed by
+    /// newlines before the next pipeline.
+    fn parse_and_or(&mut self, commands: &mut Vec<Command>) -> Result<(), SyntaxError> {
+        let mut left = vec![];
+        self.parse_pipeline(&mut left)?;
+        while let TokenKind::Operator(op @ ("&&" | "||")) = self.peek()?.kind {
+            self.next()?;
+            self.skip_newlines()?;
+            let mut right = vec![];
+            self.parse_pipeline(&mut right)?;
+            left = vec![match op {
+                "&&" => Command::And { left, right },
+                _ => Command::Or { left, right },
+            }];
+        }
+        commands.extend(left);
+        Ok(())
     }
 
-    fn handle_space(&mut self) {
-        self.flush_pending_fd_char();
+    /// Reads commands joined by `|`. Redirects of the last one apply to the
+    /// whole pipeline, while those of the others are kept with them in a
+    /// group.
+    fn parse_pipeline(&mut self, commands: &mut Vec<Command>) -> Result<(), SyntaxError> {
+        let mut stages = vec![self.parse_command()?];
+        while self.peek()?.kind == TokenKind::Operator("|") {
+            self.next()?;
+            self.skip_newlines()?;
+            stages.push(self.parse_command()?);
+        }
 
-        if self.should_append_space() {
-            if let Some(cmd) = &mut self.current_cmd {
-                cmd.append_char(' ');
-            }
+        let (last, redirects) = stages.pop().unwrap();
+        if stages.is_empty() {
+            commands.extend(last);
+        } else {
+            let programs = stages
+                .into_iter()
+                .chain([(last, vec![])])
+                .map(
+                    |(command, redirects)| match (command, redirects.is_empty()) {
+                        (Some(command), true) => Box::new(command),
+                        (command, _) => Box::new(Command::Group {
+                            body: command.into_iter().chain(redirects).collect(),
+                        }),
+                    },
+                )
+                .collect();
+            commands.push(Command::Pipe { programs });
         }
+        commands.extend(redirects);
+        Ok(())
     }
 
-    fn should_append_space(&self) -> bool {
-        match &self.current_cmd {
-            Some(Command::Program { .. }) => self
-                .current_cmd
-                .as_ref()
-                .map_or(false, |cmd| !cmd.is_empty()),
-            Some(Command::Pipe { .. }) => {
-                self.current_cmd
-                    .as_ref()
-                    .map_or(false, |cmd| !cmd.is_empty())
-                    && self.last_char != ' '
+    /// Reads a command with its redirects. A simple command made only of
+    /// redirects has no command of its own.
+    fn parse_command(&mut self) -> Result<(Option<Command>, Vec<Command>), SyntaxError> {
+        if let Some(command) = self.parse_compound()? {
+            let redirects = self.parse_redirects()?;
+            return Ok((Some(command), redirects));
+        }
+
+        let token = self.peek()?;
+        match &token.kind {
+            TokenKind::Reserved("function") => {
+                self.next()?;
+                let name = self.next()?;
+                let TokenKind::Word(name) = name.kind else {
+                    return Err(unexpected(&name));
+                };
+                if self.peek_is("(")? {
+                    self.next()?;
+                    self.expect(")")?;
+                }
+                Ok((Some(self.parse_function_body(name)?), vec![]))
             }
-            _ => false,
+            TokenKind::Word(_) | TokenKind::IoNumber(_) => self.parse_simple_command(),
+            TokenKind::Operator(op) if REDIRECT_OPERATORS.contains(op) => {
+                self.parse_simple_command()
+            }
+            _ => Err(unexpected(&self.next()?)),
         }
     }
 
-    fn handle_regular_char(&mut self, c: char) {
-        self.flush_pending_fd_char();
+    fn parse_simple_command(&mut self) -> Result<(Option<Command>, Vec<Command>), SyntaxError> {
+        let mut words = vec![];
+        let mut redirects = vec![];
+        loop {
+            match &self.peek()?.kind {
+                TokenKind::Word(word) => {
+                    words.push(normalize_word(word));
+                    self.next()?;
+                }
+                TokenKind::IoNumber(_) => redirects.push(self.parse_redirect()?),
+                TokenKind::Operator(op) if REDIRECT_OPERATORS.contains(op) => {
+                    redirects.push(self.parse_redirect()?)
+                }
+                _ => break,
+            }
+        }
 
-        if let Some(cmd) = &mut self.current_cmd {
-            cmd.append_char(c);
-        } else {
-            self.current_cmd = Some(Command::Program {
-                cmd: c.to_string(),
-                args: vec![],
-            });
+        // `name ( )` starts a function definition.
+        if let [name] = &words[..]
+            && redirects.is_empty()
+            && self.peek_is("(")?
+        {
+            let paren = self.next()?;
+            if !is_function_name(name) {
+                return Err(unexpected(&paren));
+            }
+            self.expect(")")?;
+            return Ok((Some(self.parse_function_body(name.clone())?), vec![]));
         }
+
+        let mut words = words.into_iter();
+        let command = words.next().map(|cmd| Command::Program {
+            cmd,
+            args: words.collect(),
+        });
+        Ok((command, redirects))
     }
 
-    fn finalize_current_command(&mut self) {
-        if let Some(mut cmd) = self.current_cmd.take() {
-            cmd.trim_remove_empty_args();
-            self.commands.push(cmd);
+    /// Reads the body of a function and the redirects applied to each call.
+    fn parse_function_body(&mut self, name: String) -> Result<Command, SyntaxError> {
+        self.skip_newlines()?;
+        match self.parse_compound()? {
+            Some(body) => Ok(Command::FunctionDef {
+                name,
+                body: Box::new(body),
+                redirects: self.parse_redirects()?,
+            }),
+            None => Err(unexpected(&self.next()?)),
         }
     }
 
-    fn flush_pending_fd_char(&mut self) {
-        if let Some(fd_char) = self.pending_fd_char.take() {
-            if let Some(cmd) = &mut self.current_cmd {
-                cmd.append_char(fd_char);
+    fn parse_redirects(&mut self) -> Result<Vec<Command>, SyntaxError> {
+        let mut redirects = vec![];
+        loop {
+            match &self.peek()?.kind {
+                TokenKind::IoNumber(_) => {}
+                TokenKind::Operator(op) if REDIRECT_OPERATORS.contains(op) => {}
+                _ => return Ok(redirects),
             }
+            redirects.push(self.parse_redirect()?);
         }
     }
 
-    fn finish(mut self) -> Vec<Command> {
-        self.flush_pending_fd_char();
-
-        if let Some(cmd) = self.current_cmd {
-            self.commands.push(cmd);
-        }
-
-        for cmd in self.commands.iter_mut() {
-            cmd.trim_path();
-            cmd.trim_remove_empty_args();
+    /// Reads `[n]>`, `>>`, `>|`, `>&`, `<`, `<&`, `<>`, `<<` or `<<-` and its
+    /// target, which for a here-document is its body.
+    fn parse_redirect(&mut self) -> Result<Command, SyntaxError> {
+        let mut token = self.next()?;
+        let mut fd = None;
+        if let TokenKind::IoNumber(n) = token.kind {
+            fd = Some(n);
+            token = self.next()?;
         }
+        let op = match token.kind {
+            TokenKind::Operator(
+                op @ (">" | ">|" | ">>" | ">&" | "<" | "<&" | "<>" | "<<" | "<<-"),
+            ) => op,
+            _ => return Err(unexpected(&token)),
+        };
+
+        let target = self.next()?;
+        let mut write_mode = match op {
+            ">>" => WriteFileMode::Append,
+            "<" | "<&" => WriteFileMode::Read,
+            "<>" => WriteFileMode::ReadWrite,
+            _ => WriteFileMode::OverWrite,
+        };
+        let path = match target.kind {
+            TokenKind::HereDoc { delimiter, body } => {
+                let expand = !delimiter.contains(['\'', '"', '\\']);
+                write_mode = WriteFileMode::HereDoc { expand };
+                body
+            }
+            TokenKind::Word(word) if op == ">&" || op == "<&" => format!("&{word}"),
+            TokenKind::Word(word) => word,
+            // A missing target is reported as the newline ending the line.
+            TokenKind::Eof => {
+                let kind = SyntaxErrorKind::UnexpectedToken(target.text());
+                return Err(SyntaxError::new(kind, target.span));
+            }
+            _ => return Err(unexpected(&target)),
+        };
 
-        self.commands
+        Ok(Command::RedirectTo {
+            path: match write_mode {
+                WriteFileMode::HereDoc { .. } => path,
+                _ => normalize_word(&path),
+            },
+            redirect_mode: match fd.unwrap_or(if op.starts_with('<') { 0 } else { 1 }) {
+                0 => RedirectMode::StdIn,
+                1 => RedirectMode::StdOut,
+                2 => RedirectMode::StdErr,
+                n => RedirectMode::Fd(n),
+            },
+            write_mode,
+        })
     }
-}
 
-impl Command {
-    fn append_char(&mut self, c: char) {
-        match self {
-            Command::RedirectTo { path, .. } => {
-                path.push(c);
+    /// Reads a compound command if one starts here.
+    fn parse_compound(&mut self) -> Result<Option<Command>, SyntaxError> {
+        if self.peek_is("{")? {
+            self.expect("{")?;
+            let body = self.parse_body(&["}"])?;
+            self.expect("}")?;
+            return Ok(Some(Command::Group { body }));
+        }
+        let command = match &self.peek()?.kind {
+            TokenKind::Operator("(") => {
+                self.next()?;
+                let body = self.parse_body(&[")"])?;
+                self.expect(")")?;
+                Command::Subshell { body }
+            }
+            TokenKind::Reserved("if") => self.parse_if()?,
+            TokenKind::Reserved(keyword @ ("while" | "until")) => {
+                let keyword = *keyword;
+                self.next()?;
+                let condition = self.parse_body(&["do"])?;
+                let body = self.parse_do_group()?;
+                match keyword {
+                    "while" => Command::While { condition, body },
+                    _ => Command::Until { condition, body },
+                }
+            }
+            TokenKind::Reserved("for") => self.parse_for()?,
+            TokenKind::Reserved("case") => self.parse_case()?,
+            TokenKind::Arithmetic(expression) => {
+                let expression = expression.clone();
+                self.next()?;
+                Command::Arithmetic { expression }
             }
-            Command::Program { cmd, args } => {
-                append_to_program(c, cmd, args);
+            TokenKind::Conditional(inside) => {
+                let words = conditional_words(inside);
+                self.next()?;
+                Command::Conditional { words }
             }
-            Command::Pipe { programs } => {
-                // Append to the last program in the pipe
-                if let Some(last_program) = programs.last_mut() {
-                    last_program.append_char(c);
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    fn parse_if(&mut self) -> Result<Command, SyntaxError> {
+        self.next()?;
+        let mut branches = vec![];
+        loop {
+            let condition = self.parse_body(&["then"])?;
+            self.expect("then")?;
+            let body = self.parse_body(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+
+            let token = self.next()?;
+            match token.kind {
+                TokenKind::Reserved("elif") => continue,
+                TokenKind::Reserved("else") => {
+                    let else_body = self.parse_body(&["fi"])?;
+                    self.expect("fi")?;
+                    return Ok(Command::If {
+                        branches,
+                        else_body: Some(else_body),
+                    });
+                }
+                TokenKind::Reserved("fi") => {
+                    return Ok(Command::If {
+                        branches,
+                        else_body: None,
+                    });
                 }
+                _ => return Err(unexpected(&token)),
             }
         }
     }
 
-    fn is_empty(&self) -> bool {
-        match self {
-            Command::Program { cmd, args } => cmd.is_empty() && args.is_empty(),
-            Command::Pipe { programs } => programs.iter().all(|p| p.is_empty()),
-            Command::RedirectTo { path, .. } => path.is_empty(),
-        }
+    fn parse_do_group(&mut self) -> Result<Vec<Command>, SyntaxError> {
+        self.skip_newlines()?;
+        self.expect("do")?;
+        let body = self.parse_body(&["done"])?;
+        self.expect("done")?;
+        Ok(body)
     }
 
-    fn trim_remove_empty_args(&mut self) {
-        match self {
-            Command::Program { cmd, args } => {
-                *cmd = cmd.trim().to_string();
-                args.iter_mut()
-                    .for_each(|arg| *arg = arg.trim().to_string());
-                args.retain(|arg| !arg.is_empty());
+    /// Reads `for name [in words...]` or `for ((init; condition; step))`
+    /// with the loop body.
+    fn parse_for(&mut self) -> Result<Command, SyntaxError> {
+        self.next()?;
+        let token = self.next()?;
+        let variable = match token.kind {
+            TokenKind::Arithmetic(expression) => {
+                let mut parts = expression
+                    .splitn(3, ';')
+                    .map(|part| part.trim().to_string());
+                let (init, condition, step) = (
+                    parts.next().unwrap_or_default(),
+                    parts.next().unwrap_or_default(),
+                    parts.next().unwrap_or_default(),
+                );
+                if self.peek()?.kind == TokenKind::Operator(";") {
+                    self.next()?;
+                }
+                return Ok(Command::ArithmeticFor {
+                    init,
+                    condition,
+                    step,
+                    body: self.parse_do_group()?,
+                });
             }
-            Command::Pipe { programs } => {
-                for program in programs.iter_mut() {
-                    program.trim_remove_empty_args();
+            TokenKind::Word(word) => normalize_word(&word),
+            _ => return Err(unexpected(&token)),
+        };
+
+        self.skip_newlines()?;
+        let mut words = None;
+        if self.peek_is("in")? {
+            self.next()?;
+            let mut list = vec![];
+            loop {
+                let token = self.next()?;
+                match token.kind {
+                    TokenKind::Word(word) => list.push(normalize_word(&word)),
+                    TokenKind::Operator(";") | TokenKind::Newline => break,
+                    _ => return Err(unexpected(&token)),
                 }
             }
-            _ => {}
+            words = Some(list);
+        } else if self.peek()?.kind == TokenKind::Operator(";") {
+            self.next()?;
         }
+
+        Ok(Command::For {
+            variable,
+            words,
+            body: self.parse_do_group()?,
+        })
     }
 
-    fn trim_path(&mut self) {
-        if let Command::RedirectTo { path, .. } = self {
-            *path = path.trim().to_string();
+    /// Reads `case word in [(]pattern[|pattern]...) list ;; ... esac`. The
+    /// last item may leave out its terminator.
+    fn parse_case(&mut self) -> Result<Command, SyntaxError> {
+        self.next()?;
+        let token = self.next()?;
+        let TokenKind::Word(word) = token.kind else {
+            return Err(unexpected(&token));
+        };
+        self.skip_newlines()?;
+        self.expect("in")?;
+
+        let mut items = vec![];
+        loop {
+            self.skip_newlines()?;
+            if self.peek_is("esac")? {
+                self.expect("esac")?;
+                break;
+            }
+            if self.peek()?.kind == TokenKind::Operator("(") {
+                self.next()?;
+            }
+
+            // Patterns stay as written: quoting decides which characters
+            // are matched literally.
+            let mut patterns = vec![];
+            loop {
+                let token = self.next()?;
+                match token.kind {
+                    TokenKind::Word(pattern) => patterns.push(pattern),
+                    TokenKind::Reserved(pattern) => patterns.push(pattern.to_string()),
+                    _ => return Err(unexpected(&token)),
+                }
+                let token = self.next()?;
+                match token.kind {
+                    TokenKind::Operator("|") => continue,
+                    TokenKind::Operator(")") => break,
+                    _ => return Err(unexpected(&token)),
+                }
+            }
+
+            let stops = ["esac", ";;", ";&", ";;&"];
+            let body = self.parse_list(&stops)?;
+            let terminator = match self.peek()?.kind {
+                TokenKind::Operator(";&") => CaseTerminator::FallThrough,
+                TokenKind::Operator(";;&") => CaseTerminator::Continue,
+                _ => CaseTerminator::Break,
+            };
+            if let TokenKind::Operator(op) = self.peek()?.kind
+                && CASE_TERMINATORS.contains(&op)
+            {
+                self.next()?;
+            }
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
         }
+
+        Ok(Command::Case {
+            word: normalize_word(&word),
+            items,
+        })
     }
+}
 
+/// The error for a token the grammar does not allow where it was found.
+fn unexpected(token: &Token) -> SyntaxError {
+    let kind = match token.kind {
+        TokenKind::Eof => SyntaxErrorKind::UnexpectedEof,
+        _ => SyntaxErrorKind::UnexpectedToken(token.text()),
+    };
+    SyntaxError::new(kind, token.span.clone())
+}
+
+impl Command {
     pub fn get_cmd_args(&self) -> (String, Vec<String>) {
         match self {
             Command::Program { cmd, args } => (cmd.to_string(), args.to_vec()),
@@ -245,16 +548,91 @@ impl Command {
     }
 }
 
-fn append_to_program(c: char, cmd: &mut String, args: &mut Vec<String>) {
-    if cmd.is_empty() {
-        cmd.push(c);
-    } else if c == ' ' {
-        if !args.is_empty() || !cmd.is_empty() {
-            args.push(String::new());
+/// Splits the inside of `[[ ... ]]` on unquoted blanks, with grouping
+/// parentheses as words of their own. The words stay quoted, since quoting
+/// decides how `==` and `=~` operands match.
+fn conditional_words(text: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quotes: Vec<char> = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let innermost = quotes.last().copied();
+        let regex = words.last().is_some_and(|last| last == "=~");
+        match (innermost, c) {
+            (None, ' ' | '\t' | '\n') => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            (Some('\''), '\'') | (Some('"'), '"') | (Some('`'), '`') | (Some('('), ')') => {
+                quotes.pop();
+            }
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                word.push(c);
+                word.extend(chars.next());
+                continue;
+            }
+            (None | Some('('), '\'' | '"' | '`') | (Some('"'), '`') => quotes.push(c),
+            (_, '(') if word.ends_with('$') || innermost == Some('(') => quotes.push(c),
+            // Extended globs such as `@(a|b)` and the groups of a regular
+            // expression after `=~` keep their parentheses.
+            (None, '(') if word.ends_with(['@', '?', '*', '+', '!']) || regex => quotes.push(c),
+            (None, '(' | ')') => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                words.push(c.to_string());
+                continue;
+            }
+            _ => {}
         }
-    } else if let Some(last_arg) = args.last_mut() {
-        last_arg.push(c);
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Whether `name` can be defined as a function, as in `name() { ...; }`.
+fn is_function_name(name: &str) -> bool {
+    !name.contains(['\'', '"', '\\', '$', '`', '='])
+}
+
+/// Words keep their quotes only when removing them would change how the
+/// word is expanded later on; plain words are stored already unquoted.
+fn normalize_word(word: &str) -> String {
+    let unquoted = remove_quotes(word);
+    // An empty quoted word such as `""` is still an argument.
+    if unquoted.is_empty() || unquoted.chars().any(|c| EXPANSION_CHARS.contains(&c)) {
+        word.to_string()
     } else {
-        cmd.push(c);
+        unquoted
+    }
+}
+
+const EXPANSION_CHARS: [char; 7] = ['\'', '"', '\\', '~', '$', '`', '='];
+
+pub fn remove_quotes(word: &str) -> String {
+    let mut unquoted = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = word.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '\\') => unquoted.extend(chars.next()),
+            (Some('"'), '\\') => match chars.peek() {
+                Some('$' | '`' | '"' | '\\') => unquoted.extend(chars.next()),
+                _ => unquoted.push(c),
+            },
+            _ => unquoted.push(c),
+        }
     }
+    unquoted
 }
@@ -0,0 +1,558 @@
+/// The result of formatting `printf` arguments: the output, and the
+/// complaints about arguments that were not valid numbers.
+#[derive(Debug, Default, PartialEq)]
+pub struct Formatted {
+    pub output: Vec<u8>,
+    pub errors: Vec<String>,
+}
+
+/// Formats `args` as `printf` does, reusing `format` as long as some
+/// arguments remain. Missing arguments read as empty strings or zero.
+pub fn format(format: &str, args: &[String]) -> Formatted {
+    let mut formatter = Formatter {
+        args,
+        next: 0,
+        formatted: Formatted::default(),
+        stopped: false,
+    };
+
+    loop {
+        let consumed = formatter.next;
+        formatter.run(format);
+        if formatter.stopped || formatter.next >= args.len() || formatter.next == consumed {
+            break;
+        }
+    }
+    formatter.formatted
+}
+
+/// Expands the backslash escapes of `echo -e` (`echo` is true) or of the
+/// arguments of `printf %b`, in which octal escapes start with `\0`.
+/// Returns the bytes and whether a `\c` asked to stop all output.
+pub fn expand_escapes(text: &str, echo: bool) -> (Vec<u8>, bool) {
+    let mut output = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut output, c);
+            continue;
+        }
+        match chars.peek() {
+            Some('c') => return (output, true),
+            Some('0') => {
+                chars.next();
+                let value = take_digits(&mut chars, 8, 3);
+                output.push(value.unwrap_or(0) as u8);
+            }
+            // `printf %b` also takes octal escapes without the leading 0.
+            Some('1'..='7') if !echo => {
+                let value = take_digits(&mut chars, 8, 3);
+                output.push(value.unwrap_or(0) as u8);
+            }
+            _ => expand_escape(&mut chars, &mut output),
+        }
+    }
+    (output, false)
+}
+
+/// Expands the backslash escapes of a `$'...'` string: those of format
+/// strings, plus `\'`, `\"` and `\?`. As in bash, a NUL byte ends it.
+pub fn expand_ansi_c(text: &str) -> Vec<u8> {
+    let mut output = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut output, c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some(quoted @ ('\'' | '"' | '?')) => {
+                chars.next();
+                output.push(quoted as u8);
+            }
+            Some('0'..='7') => {
+                let value = take_digits(&mut chars, 8, 3);
+                output.push(value.unwrap_or(0) as u8);
+            }
+            _ => expand_escape(&mut chars, &mut output),
+        }
+    }
+    if let Some(nul) = output.iter().position(|&b| b == 0) {
+        output.truncate(nul);
+    }
+    output
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// Expands the escape after a backslash common to format strings, `%b`
+/// and `echo -e`: the C ones, `\e`, `\xHH`, `\uHHHH` and `\UHHHHHHHH`.
+/// An unknown escape is kept as is.
+fn expand_escape(chars: &mut Chars, output: &mut Vec<u8>) {
+    let Some(c) = chars.next() else {
+        output.push(b'\\');
+        return;
+    };
+    let byte = match c {
+        '\\' => b'\\',
+        'a' => 0x07,
+        'b' => 0x08,
+        'e' | 'E' => 0x1b,
+        'f' => 0x0c,
+        'n' => b'\n',
+        'r' => b'\r',
+        't' => b'\t',
+        'v' => 0x0b,
+        'x' | 'u' | 'U' => {
+            let max = match c {
+                'x' => 2,
+                'u' => 4,
+                _ => 8,
+            };
+            match take_digits(chars, 16, max) {
+                Some(value) if c == 'x' => value as u8,
+                Some(value) => {
+                    push_char(output, char::from_u32(value).unwrap_or('\u{fffd}'));
+                    return;
+                }
+                None => {
+                    output.push(b'\\');
+                    push_char(output, c);
+                    return;
+                }
+            }
+        }
+        _ => {
+            output.push(b'\\');
+            push_char(output, c);
+            return;
+        }
+    };
+    output.push(byte);
+}
+
+/// Reads up to `max` digits in `radix`, or `None` if there are none.
+fn take_digits(chars: &mut Chars, radix: u32, max: usize) -> Option<u32> {
+    let mut value = None;
+    for _ in 0..max {
+        let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+        chars.next();
+        value = Some(
+            value
+                .unwrap_or(0u32)
+                .saturating_mul(radix)
+                .saturating_add(digit),
+        );
+    }
+    value
+}
+
+fn push_char(output: &mut Vec<u8>, c: char) {
+    output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+struct Formatter<'a> {
+    args: &'a [String],
+    next: usize,
+    formatted: Formatted,
+    // Set by `\c` in a `%b` argument.
+    stopped: bool,
+}
+
+/// A conversion specification such as `%-8.3s`.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl Formatter<'_> {
+    /// Formats the whole of `format` once.
+    fn run(&mut self, format: &str) {
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek().is_some_and(|c| c.is_digit(8)) => {
+                    let value = take_digits(&mut chars, 8, 3);
+                    self.formatted.output.push(value.unwrap_or(0) as u8);
+                }
+                '\\' => expand_escape(&mut chars, &mut self.formatted.output),
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    self.formatted.output.push(b'%');
+                }
+                '%' => match self.parse_spec(&mut chars) {
+                    Some(spec) => {
+                        self.convert(&spec);
+                        if self.stopped {
+                            return;
+                        }
+                    }
+                    None if self.stopped => return,
+                    None => self.formatted.output.push(b'%'),
+                },
+                c => push_char(&mut self.formatted.output, c),
+            }
+        }
+    }
+
+    /// Parses the flags, width, precision and conversion after a `%`.
+    /// Returns `None`, leaving the rest as plain text, when the conversion
+    /// is missing or unknown.
+    fn parse_spec(&mut self, chars: &mut Chars) -> Option<Spec> {
+        let mut spec = Spec::default();
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        // A `*` takes the width or precision from the arguments.
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = self.next_integer();
+            spec.left |= width < 0;
+            spec.width = Some(self.checked_size(width.unsigned_abs())?);
+        } else if let Some(width) = take_digits(chars, 10, usize::MAX) {
+            spec.width = Some(self.checked_size(width.into())?);
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                // A negative precision is as if none was given.
+                if let Ok(precision) = u64::try_from(self.next_integer()) {
+                    spec.precision = Some(self.checked_size(precision)?);
+                }
+            } else {
+                let precision = take_digits(chars, 10, usize::MAX).unwrap_or(0);
+                spec.precision = Some(self.checked_size(precision.into())?);
+            }
+        }
+
+        // Length modifiers mean nothing here.
+        while chars.peek().is_some_and(|c| "hlLjzt".contains(*c)) {
+            chars.next();
+        }
+
+        let conversion = chars.next()?;
+        if !"diouxXfFeEgGcsbq".contains(conversion) {
+            return None;
+        }
+        spec.conversion = conversion;
+        Some(spec)
+    }
+
+    /// A width or precision, which like in C may not exceed `i32::MAX`.
+    /// Larger ones stop all output rather than padding by gigabytes.
+    fn checked_size(&mut self, size: u64) -> Option<usize> {
+        if size > i32::MAX as u64 {
+            let error = format!("{size}: Numerical result out of range");
+            self.formatted.errors.push(error);
+            self.stopped = true;
+            return None;
+        }
+        Some(size as usize)
+    }
+
+    fn next_arg(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.next)?;
+        self.next += 1;
+        Some(arg)
+    }
+
+    /// The next argument as an integer: a decimal, octal or hexadecimal
+    /// number, or `'c` for the code of the character `c`.
+    fn next_integer(&mut self) -> i64 {
+        let Some(arg) = self.next_arg().map(String::from) else {
+            return 0;
+        };
+        if let Some(value) = character_code(&arg) {
+            return value;
+        }
+
+        let text = arg.trim_start();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let parsed = if let Some(hex) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            u64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            u64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse::<u64>()
+        };
+
+        match parsed {
+            Ok(value) if negative => (value as i64).wrapping_neg(),
+            Ok(value) => value as i64,
+            Err(_) => {
+                self.formatted.errors.push(format!("{arg}: invalid number"));
+                0
+            }
+        }
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let Some(arg) = self.next_arg().map(String::from) else {
+            return 0.0;
+        };
+        if let Some(value) = character_code(&arg) {
+            return value as f64;
+        }
+        match arg.trim().parse::<f64>() {
+            Ok(value) => value,
+            Err(_) => {
+                self.formatted.errors.push(format!("{arg}: invalid number"));
+                0.0
+            }
+        }
+    }
+
+    fn convert(&mut self, spec: &Spec) {
+        let text = match spec.conversion {
+            'd' | 'i' | 'o' | 'u' | 'x' | 'X' => {
+                let value = self.next_integer();
+                return self.push_number(spec, format_integer(value, spec));
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.next_float();
+                return self.push_number(spec, format_float(value, spec));
+            }
+            'c' => self
+                .next_arg()
+                .and_then(|arg| arg.chars().next())
+                .map(String::from)
+                .unwrap_or_default(),
+            's' => {
+                let arg = self.next_arg().unwrap_or_default();
+                match spec.precision {
+                    Some(precision) => arg.chars().take(precision).collect(),
+                    None => arg.to_string(),
+                }
+            }
+            'q' => quote(self.next_arg().unwrap_or_default()),
+            _ => {
+                let (bytes, stop) = expand_escapes(self.next_arg().unwrap_or_default(), false);
+                self.stopped = stop;
+                let mut text = String::from_utf8_lossy(&bytes).into_owned();
+                if let Some(precision) = spec.precision {
+                    text = text.chars().take(precision).collect();
+                }
+                // Keep bytes that are not UTF-8, such as those of `\xff`,
+                // unless padding is needed.
+                if spec.width.is_none_or(|width| width <= text.chars().count()) {
+                    if spec.precision.is_none() {
+                        self.formatted.output.extend(bytes);
+                    } else {
+                        self.formatted.output.extend(text.bytes());
+                    }
+                    return;
+                }
+                text
+            }
+        };
+        let padded = pad(String::new(), &text, spec, false);
+        self.formatted.output.extend(padded.bytes());
+    }
+
+    fn push_number(&mut self, spec: &Spec, (prefix, digits): (String, String)) {
+        let zero_ok = !(spec.precision.is_some() && "diouxX".contains(spec.conversion));
+        let padded = pad(prefix, &digits, spec, zero_ok);
+        self.formatted.output.extend(padded.bytes());
+    }
+}
+
+/// The value of `'c` or `"c`: the code of the character `c`.
+fn character_code(arg: &str) -> Option<i64> {
+    let rest = arg.strip_prefix(['\'', '"'])?;
+    Some(rest.chars().next().map_or(0, |c| c as i64))
+}
+
+/// Pads `prefix` (a sign or `0x`) and `body` to the width of `spec`, with
+/// zeros between them when asked for and `zero_ok`.
+fn pad(prefix: String, body: &str, spec: &Spec, zero_ok: bool) -> String {
+    let len = prefix.chars().count() + body.chars().count();
+    let fill = spec.width.unwrap_or(0).saturating_sub(len);
+
+    if spec.left {
+        format!("{prefix}{body}{}", " ".repeat(fill))
+    } else if spec.zero && zero_ok {
+        format!("{prefix}{}{body}", "0".repeat(fill))
+    } else {
+        format!("{}{prefix}{body}", " ".repeat(fill))
+    }
+}
+
+fn sign(negative: bool, spec: &Spec) -> String {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+    .to_string()
+}
+
+/// Formats an integer conversion as its sign or base prefix, and digits.
+fn format_integer(value: i64, spec: &Spec) -> (String, String) {
+    let unsigned = value as u64;
+    let mut digits = match spec.conversion {
+        'd' | 'i' => value.unsigned_abs().to_string(),
+        'o' => format!("{unsigned:o}"),
+        'x' => format!("{unsigned:x}"),
+        'X' => format!("{unsigned:X}"),
+        _ => unsigned.to_string(),
+    };
+    match spec.precision {
+        Some(0) if value == 0 => digits.clear(),
+        Some(precision) if precision > digits.len() => {
+            digits.insert_str(0, &"0".repeat(precision - digits.len()));
+        }
+        _ => {}
+    }
+
+    let prefix = match spec.conversion {
+        'd' | 'i' => sign(value < 0, spec),
+        'o' if spec.alternate && !digits.starts_with('0') => {
+            digits.insert(0, '0');
+            String::new()
+        }
+        'x' if spec.alternate && value != 0 => String::from("0x"),
+        'X' if spec.alternate && value != 0 => String::from("0X"),
+        _ => String::new(),
+    };
+    (prefix, digits)
+}
+
+/// Formats a floating point conversion as its sign, and digits.
+fn format_float(value: f64, spec: &Spec) -> (String, String) {
+    let upper = spec.conversion.is_ascii_uppercase();
+    let prefix = sign(value.is_sign_negative() && !value.is_nan(), spec);
+    let value = value.abs();
+
+    let digits = if value.is_infinite() {
+        String::from("inf")
+    } else if value.is_nan() {
+        String::from("nan")
+    } else {
+        let precision = spec.precision.unwrap_or(6);
+        match spec.conversion.to_ascii_lowercase() {
+            'f' => format!("{value:.precision$}"),
+            'e' => exponent_notation(value, precision),
+            _ => general_notation(value, precision, spec.alternate),
+        }
+    };
+
+    let digits = if upper { digits.to_uppercase() } else { digits };
+    (prefix, digits)
+}
+
+/// `%e`: one digit, the fraction and an exponent of at least two digits.
+fn exponent_notation(value: f64, precision: usize) -> String {
+    let formatted = format!("{value:.precision$e}");
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.unsigned_abs())
+}
+
+/// `%g`: `%e` for very small or large values and `%f` otherwise, with
+/// `precision` significant digits and no trailing zeros unless `#` is given.
+fn general_notation(value: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if value == 0.0 {
+        0
+    } else {
+        let formatted = format!("{value:.*e}", precision - 1);
+        formatted
+            .split_once('e')
+            .and_then(|(_, exponent)| exponent.parse::<i64>().ok())
+            .unwrap_or(0)
+    };
+
+    let formatted = if exponent < -4 || exponent >= precision as i64 {
+        exponent_notation(value, precision - 1)
+    } else {
+        let decimals = (precision as i64 - 1 - exponent) as usize;
+        format!("{value:.decimals$}")
+    };
+    if alternate {
+        return formatted;
+    }
+
+    // Strip trailing zeros from the fraction, keeping any exponent.
+    let (number, exponent) = match formatted.find('e') {
+        Some(pos) => formatted.split_at(pos),
+        None => (formatted.as_str(), ""),
+    };
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+    format!("{number}{exponent}")
+}
+
+/// `%q`: quotes `text` so that the shell reads it back as one word.
+fn quote(text: &str) -> String {
+    if text.is_empty() {
+        return String::from("''");
+    }
+    if text.chars().any(|c| c.is_control()) {
+        let mut quoted = String::from("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+
+    let mut quoted = String::new();
+    for (idx, c) in text.chars().enumerate() {
+        // A tilde is only special at the start of a word.
+        let special =
+            !(c.is_alphanumeric() || "_./-=+:,@%^~".contains(c)) || (idx == 0 && c == '~');
+        if special {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
@@ -0,0 +1,258 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+
+use crate::builtins::Registry;
+use crate::enums::Command;
+use crate::error::ShellError;
+use crate::interpret;
+use crate::jobs::{self, Job};
+use crate::parse;
+use crate::state::{FlowControl, ShellOptions, ShellState};
+use crate::utils;
+
+/// A shell to embed in other programs: it owns its variables, functions,
+/// history, background jobs, builtins, options and working directory, and
+/// runs commands with them as the interactive shell does.
+///
+/// Commands run with the process' real file descriptors, so their output
+/// goes to its standard output and error unless collected with
+/// [`Shell::capture`]. The process' working directory is set to the
+/// shell's before running anything.
+///
+/// The working directory and file descriptors are process-wide: redirects
+/// and `cd` change them for every thread while a command runs, so two
+/// shells must not run commands at the same time.
+#[derive(Debug, Clone)]
+pub struct Shell {
+    state: ShellState,
+}
+
+/// What a command captured by [`Shell::capture`] wrote, and its status.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Output {
+    pub status: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    pub fn stdout_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+impl Shell {
+    /// A shell whose variables are the exported process environment, with
+    /// the standard builtins and the process' working directory.
+    pub fn new() -> Self {
+        Shell {
+            state: ShellState::new(),
+        }
+    }
+
+    /// Runs a command line as typed at the prompt, adding it to the
+    /// history. Returns the status of its last command.
+    pub fn run_line(&mut self, line: &str) -> i32 {
+        let entry = line.trim();
+        if !entry.is_empty() {
+            self.state.history.push(entry.to_string());
+        }
+        self.run_script(line)
+    }
+
+    /// Runs the commands of a script, until its end or `exit`. Returns the
+    /// status of the last command.
+    pub fn run_script(&mut self, script: &str) -> i32 {
+        self.enter_cwd();
+        interpret::run_script(script, &mut self.state)
+    }
+
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> io::Result<i32> {
+        self.enter_cwd();
+        interpret::run_file(path.as_ref(), &mut self.state)
+    }
+
+    /// Runs `cmd` in a copy of the shell, as for `$(cmd)`, collecting its
+    /// standard output and error. The shell itself is left unchanged.
+    ///
+    /// The copy is a forked child process, which only inherits the thread
+    /// calling this; the process' working directory is still changed to
+    /// the shell's.
+    pub fn capture(&mut self, cmd: &str) -> Output {
+        self.enter_cwd();
+        let captured = parse::run_parser(cmd)
+            .map_err(ShellError::from)
+            .and_then(|commands| capture_streams(commands, &mut self.state));
+        match captured {
+            Ok(output) => output,
+            Err(error) => Output {
+                status: error.status(),
+                stdout: vec![],
+                stderr: format!("{error}\n").into_bytes(),
+            },
+        }
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.state.get_var(name)
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.state.set_var(name, value);
+    }
+
+    /// Assigns a variable and hands it to the programs the shell runs.
+    pub fn export_var(&mut self, name: &str, value: &str) {
+        self.state.set_var(name, value);
+        self.state.export_var(name);
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.state.unset_var(name);
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Command> {
+        self.state.functions.get(name)
+    }
+
+    /// The jobs started with `&` that were not yet waited for, or reported
+    /// done by `jobs`, oldest first.
+    pub fn jobs(&mut self) -> &[Job] {
+        jobs::update(&mut self.state);
+        &self.state.jobs
+    }
+
+    /// Waits for all background jobs, as `wait` does.
+    pub fn wait_jobs(&mut self) {
+        jobs::wait_all(&mut self.state);
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.state.history
+    }
+
+    pub fn builtins(&self) -> &Registry {
+        &self.state.builtins
+    }
+
+    /// The builtins, to which more can be added with
+    /// [`Registry::register`].
+    pub fn builtins_mut(&mut self) -> &mut Registry {
+        &mut self.state.builtins
+    }
+
+    pub fn options(&self) -> &ShellOptions {
+        &self.state.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut ShellOptions {
+        &mut self.state.options
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.state.cwd
+    }
+
+    /// Changes the working directory, as `cd` does. This is also the
+    /// process' working directory, shared by its other threads.
+    pub fn set_cwd(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.state.cwd.join(path);
+        env::set_current_dir(&path)?;
+        let previous = std::mem::replace(&mut self.state.cwd, env::current_dir()?);
+        self.state.set_var("OLDPWD", &previous.to_string_lossy());
+        let pwd = self.state.cwd.to_string_lossy().into_owned();
+        self.state.set_var("PWD", &pwd);
+        Ok(())
+    }
+
+    /// The status of the last command run.
+    pub fn last_status(&self) -> i32 {
+        self.state.last_status
+    }
+
+    /// The status `exit` was called with, after which the shell should
+    /// not run anything more.
+    pub fn exit_status(&self) -> Option<i32> {
+        match self.state.flow_control {
+            Some(FlowControl::Exit(status)) => Some(status),
+            _ => None,
+        }
+    }
+
+    pub fn state(&self) -> &ShellState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut ShellState {
+        &mut self.state
+    }
+
+    /// Makes the shell's working directory the process' one, which another
+    /// shell may have changed.
+    fn enter_cwd(&self) {
+        if env::current_dir().ok().as_deref() != Some(self.state.cwd.as_path()) {
+            let _ = env::set_current_dir(&self.state.cwd);
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new()
+    }
+}
+
+impl From<ShellState> for Shell {
+    fn from(state: ShellState) -> Self {
+        Shell { state }
+    }
+}
+
+/// Runs `commands` in a forked child with its standard output and error
+/// connected to pipes, reading both until the child is done.
+fn capture_streams(commands: Vec<Command>, state: &mut ShellState) -> Result<Output, ShellError> {
+    let _ = io::stdout().flush();
+    let pipe = || utils::pipe().map_err(|error| ShellError::io("pipe", error));
+    let (mut stdout_reader, stdout_writer) = pipe()?;
+    let (stderr_reader, stderr_writer) = pipe()?;
+
+    // The closure owns the write ends, so the parent's copies are closed as
+    // soon as the fork returns and the reads see EOF.
+    let pid = utils::fork_with(move || {
+        utils::redirect_fd(&stdout_writer, libc::STDOUT_FILENO);
+        utils::redirect_fd(&stderr_writer, libc::STDERR_FILENO);
+        drop((stdout_writer, stderr_writer));
+        interpret::interpret_command(commands, state)
+    });
+
+    // Read standard error meanwhile, so that a child filling that pipe
+    // cannot block.
+    let stderr = thread::spawn(move || read_all(stderr_reader));
+    let mut stdout = vec![];
+    let read = stdout_reader.read_to_end(&mut stdout);
+    let stderr = stderr.join().unwrap_or_default();
+
+    let Some(pid) = pid else {
+        return Err(ShellError::io("capture", io::Error::other("fork failed")));
+    };
+    let status = utils::wait_pid(pid);
+    read.map_err(|error| ShellError::io("stdout", error))?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+fn read_all(mut file: File) -> Vec<u8> {
+    let mut buffer = vec![];
+    let _ = file.read_to_end(&mut buffer);
+    buffer
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::env;
+use std::os::fd::RawFd;
+use std::path::PathBuf;
+
+use crate::builtins::Registry;
+use crate::enums::Command;
+use crate::jobs::Job;
+
+/// A pending `break N`, `continue N` or `return N`, unwinding the
+/// commands still running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowControl {
+    Break(usize),
+    Continue(usize),
+    Return(i32),
+    Exit(i32),
+}
+
+/// How the shell was started, as set on its command line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShellOptions {
+    // Commands are read from a terminal, prompting for each.
+    pub interactive: bool,
+    pub login: bool,
+    // Follow POSIX where bash differs, as when run as `sh`.
+    pub posix: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub value: String,
+    pub exported: bool,
+}
+
+/// Everything the interpreter needs to remember between commands.
+#[derive(Debug, Clone, Default)]
+pub struct ShellState {
+    vars: HashMap<String, Variable>,
+    // Indexed arrays, such as those filled by `read -a`.
+    arrays: HashMap<String, Vec<String>>,
+    // `$0`: the script being run, or the shell itself.
+    pub script_name: String,
+    pub positional: Vec<String>,
+    pub last_status: i32,
+    // The status of the last command substitution in the words of the
+    // running simple command, which it reports when it only assigns.
+    pub substitution_status: Option<i32>,
+    // `$$` stays the pid of the main shell, even in forked children.
+    pub shell_pid: u32,
+    // Our ends of the `<(...)`/`>(...)` pipes and the processes behind them,
+    // released once the command using them is done.
+    pub process_substitutions: Vec<(RawFd, libc::pid_t)>,
+    // The commands started with `&`, oldest first.
+    pub jobs: Vec<Job>,
+    // `$!`: the process of the last of them.
+    pub last_background_pid: Option<libc::pid_t>,
+    // How many loops enclose the running command.
+    pub loop_depth: usize,
+    pub flow_control: Option<FlowControl>,
+    // How many `.` scripts are running, which `return` may leave.
+    pub source_depth: usize,
+    // Function definitions by name.
+    pub functions: HashMap<String, Command>,
+    // The command lines entered so far, oldest first.
+    pub history: Vec<String>,
+    pub builtins: Registry,
+    pub options: ShellOptions,
+    // The working directory as the shell knows it, which `cd` changes.
+    pub cwd: PathBuf,
+    // One scope per running function call, holding the previous values of
+    // the variables it made `local`.
+    local_scopes: Vec<HashMap<String, Option<Variable>>>,
+}
+
+impl ShellState {
+    /// A fresh state whose variables are the exported process environment.
+    pub fn new() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let var = Variable {
+                    value,
+                    exported: true,
+                };
+                (name, var)
+            })
+            .collect();
+
+        ShellState {
+            vars,
+            arrays: HashMap::new(),
+            script_name: env::args().next().unwrap_or_default(),
+            positional: vec![],
+            last_status: 0,
+            substitution_status: None,
+            shell_pid: std::process::id(),
+            process_substitutions: vec![],
+            jobs: vec![],
+            last_background_pid: None,
+            loop_depth: 0,
+            flow_control: None,
+            source_depth: 0,
+            functions: HashMap::new(),
+            history: vec![],
+            builtins: Registry::new(),
+            options: ShellOptions::default(),
+            cwd: env::current_dir().unwrap_or_default(),
+            local_scopes: vec![],
+        }
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Assigns a variable, keeping its exported flag if it already exists.
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value.to_string(),
+            None => {
+                let var = Variable {
+                    value: value.to_string(),
+                    exported: false,
+                };
+                self.vars.insert(name.to_string(), var);
+            }
+        }
+    }
+
+    pub fn export_var(&mut self, name: &str) {
+        if let Some(var) = self.vars.get_mut(name) {
+            var.exported = true;
+        }
+    }
+
+    /// Turns a forked copy of the shell into a fresh one about to run a
+    /// script: functions and running calls or loops are forgotten.
+    pub fn reset_for_script(&mut self) {
+        self.functions.clear();
+        self.local_scopes.clear();
+        self.loop_depth = 0;
+        self.source_depth = 0;
+        self.flow_control = None;
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.remove(name);
+        self.arrays.remove(name);
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<&[String]> {
+        self.arrays.get(name).map(Vec::as_slice)
+    }
+
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) {
+        self.vars.remove(name);
+        self.arrays.insert(name.to_string(), values);
+    }
+
+    /// The environment handed to external programs.
+    pub fn exported_vars(&self) -> Vec<(String, String)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.clone(), var.value.clone()))
+            .collect()
+    }
+
+    pub fn in_function(&self) -> bool {
+        !self.local_scopes.is_empty()
+    }
+
+    pub fn push_scope(&mut self) {
+        self.local_scopes.push(HashMap::new());
+    }
+
+    /// Ends a function call, restoring the variables it made local.
+    pub fn pop_scope(&mut self) {
+        for (name, previous) in self.local_scopes.pop().unwrap_or_default() {
+            match previous {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// Makes `name` local to the running function: it starts out unset
+    /// and gets its value back once the function returns. Functions called
+    /// from there on see the local variable (dynamic scoping).
+    pub fn make_local(&mut self, name: &str) {
+        let Some(scope) = self.local_scopes.last_mut() else {
+            return;
+        };
+        if !scope.contains_key(name) {
+            scope.insert(name.to_string(), self.vars.remove(name));
+        }
+    }
+}
@@ -1,82 +1,447 @@
 use std::env;
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::ffi::{CStr, CString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use crate::enums::WriteFileMode;
+use crate::error::ShellError;
 
 pub fn strip_bytes(mut bytes: Vec<u8>) -> Vec<u8> {
-    if matches!(bytes.first(), Some(b'\'') | Some(b'"')) {
-        if let Some(pos) = bytes.iter().rposition(|&b| b == b'"' || b == b'\'') {
-            bytes.remove(pos);
-            bytes.remove(0);
-        }
+    if matches!(bytes.first(), Some(b'\'') | Some(b'"'))
+        && let Some(pos) = bytes.iter().rposition(|&b| b == b'"' || b == b'\'')
+    {
+        bytes.remove(pos);
+        bytes.remove(0);
     }
     bytes
 }
 
-pub fn read_from_file<P: AsRef<Path>>(maybe_path: P) -> String {
+pub fn read_from_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
     let mut buffer = String::new();
     let mut options = OpenOptions::new();
-    let mut file = options.read(true).open(maybe_path).unwrap();
-    file.read_to_string(&mut buffer).unwrap();
-    buffer
+    let mut file = options.read(true).open(path)?;
+    file.read_to_string(&mut buffer)?;
+    Ok(buffer)
 }
 
-pub fn fill_history<P: AsRef<Path>>(pathref: P, history: &mut Vec<String>) {
-    let lines: Vec<String> = read_from_file(pathref)
-        .lines()
-        .map(|x| x.to_string())
-        .collect();
-    for line in lines {
-        history.push(line);
-    }
+pub fn fill_history<P: AsRef<Path>>(
+    pathref: P,
+    history: &mut Vec<String>,
+) -> Result<(), ShellError> {
+    let path = pathref.as_ref();
+    let contents =
+        read_from_file(path).map_err(|error| ShellError::io(path.display().to_string(), error))?;
+    history.extend(contents.lines().map(|x| x.to_string()));
+    Ok(())
 }
 
-pub fn dump_history<P: AsRef<Path>>(pathref: P, history: &mut Vec<String>, mode: WriteFileMode) {
+/// Writes the history to a file, or appends it and then forgets it, so
+/// that the next append only writes the lines entered since. On failure
+/// the history is kept.
+pub fn dump_history<P: AsRef<Path>>(
+    pathref: P,
+    history: &mut Vec<String>,
+    mode: WriteFileMode,
+) -> Result<(), ShellError> {
+    let path = pathref.as_ref();
     let mut joined = history.join("\n");
     joined.push('\n');
-    let contents: &[u8] = joined.as_bytes();
+    let append = mode == WriteFileMode::Append;
+    write_to_file(joined.as_bytes(), path, mode)
+        .map_err(|error| ShellError::io(path.display().to_string(), error))?;
+    if append {
+        history.clear();
+    }
+    Ok(())
+}
+
+pub fn write_to_file<P: AsRef<Path>>(
+    contents: &[u8],
+    path: P,
+    mode: WriteFileMode,
+) -> io::Result<()> {
+    let mut file = open_for_redirect(path, mode)?;
+    file.write_all(contents)
+}
+
+/// Opens the target of a redirect: `<` needs an existing file, which `<>`
+/// and the output redirects create.
+pub fn open_for_redirect<P: AsRef<Path>>(path: P, mode: WriteFileMode) -> io::Result<File> {
+    let mut options = OpenOptions::new();
     match mode {
-        WriteFileMode::Append => {
-            write_to_file(contents, pathref, WriteFileMode::Append);
-            history.clear();
+        WriteFileMode::Read => options.read(true),
+        WriteFileMode::ReadWrite => options.read(true).write(true).create(true),
+        WriteFileMode::Append => options.append(true).create(true),
+        WriteFileMode::OverWrite => options.write(true).truncate(true).create(true),
+        // A here-document has a body rather than a path, see
+        // [`here_doc_file`].
+        WriteFileMode::HereDoc { .. } => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+    options.open(path)
+}
+
+/// An unnamed file holding `body`, read from its start, which a
+/// here-document redirects a descriptor to.
+pub fn here_doc_file(body: &str) -> io::Result<File> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("shell-here-doc-{}-{count}", std::process::id()));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(body.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+pub fn find_executable_on_path(cmd_name: &str) -> Option<PathBuf> {
+    let path_var = env::var("PATH").ok()?;
+    find_executable_in(cmd_name, &path_var)
+}
+
+pub fn find_executable_in(cmd_name: &str, path_var: &str) -> Option<PathBuf> {
+    for path in env::split_paths(path_var) {
+        let full_path = path.join(cmd_name);
+
+        if let Ok(metadata) = full_path.metadata()
+            && metadata.is_file()
+            && (metadata.permissions().mode() & 0o111 != 0)
+        {
+            return Some(full_path);
         }
-        WriteFileMode::OverWrite => {
-            write_to_file(contents, pathref, WriteFileMode::OverWrite);
+    }
+    None
+}
+
+/// Finds a regular file, executable or not, in the directories of a
+/// `PATH`-like variable, as `.` does.
+pub fn find_file_in(name: &str, path_var: &str) -> Option<PathBuf> {
+    env::split_paths(path_var)
+        .map(|path| path.join(name))
+        .find(|full_path| full_path.is_file())
+}
+
+/// Runs a program to completion. Failing to start it, for example with
+/// `ENOEXEC` for a script without `#!` line, is left to the caller.
+pub fn run_cmd(
+    arg0: &str,
+    program: &Path,
+    args: &[&str],
+    env: &[(String, String)],
+) -> Result<i32, ShellError> {
+    let status = Command::new(program)
+        .arg0(arg0)
+        .args(args)
+        .env_clear()
+        .envs(env.iter().cloned())
+        .status()
+        .map_err(|source| ShellError::Spawn {
+            cmd: arg0.to_string(),
+            source,
+        })?;
+    Ok(status_code(status))
+}
+
+/// Exit code as reported by `$?`: signals map to 128 + the signal number.
+pub fn status_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+pub fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))) }
+}
+
+/// Duplicates `fd` out of the way so that it can be restored after a
+/// redirection, or returns `None` when it is not open.
+pub fn save_fd(fd: RawFd) -> io::Result<Option<OwnedFd>> {
+    let saved = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+    if saved < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::EBADF) => Ok(None),
+            _ => Err(error),
+        };
+    }
+    Ok(Some(unsafe { OwnedFd::from_raw_fd(saved) }))
+}
+
+/// Makes `target` a copy of the open descriptor `source`, as `>&N` does.
+pub fn duplicate_fd(source: RawFd, target: RawFd) -> io::Result<()> {
+    if unsafe { libc::dup2(source, target) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lets `fd` survive into programs spawned by the shell.
+pub fn clear_cloexec(fd: RawFd) {
+    unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+}
+
+pub fn close_fd(fd: RawFd) {
+    unsafe { libc::close(fd) };
+}
+
+pub fn redirect_fd(source: &impl AsRawFd, target: RawFd) {
+    unsafe { libc::dup2(source.as_raw_fd(), target) };
+}
+
+/// Runs `child` in a forked copy of the shell, which exits with the
+/// returned status. Returns the child's pid in the parent.
+pub fn fork_with<F: FnOnce() -> i32>(child: F) -> Option<libc::pid_t> {
+    match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!("{}", ShellError::io("fork", io::Error::last_os_error()));
+            None
+        }
+        0 => {
+            let status = child();
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            unsafe { libc::_exit(status) }
         }
+        pid => Some(pid),
     }
 }
 
-pub fn write_to_file<P: AsRef<Path>>(contents: &[u8], path: P, mode: WriteFileMode) {
-    let mut options = OpenOptions::new();
-    options.create(true);
-    if mode == WriteFileMode::Append {
-        options.append(true);
+pub fn wait_pid(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+            return status_code(ExitStatus::from_raw(status));
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 1;
+        }
+    }
+}
+
+/// The status of `pid` if it has exited, without waiting for it. Nothing
+/// is known of a process that is not a child, as in a forked copy of the
+/// shell that started it.
+pub fn try_wait_pid(pid: libc::pid_t) -> Option<i32> {
+    let mut status = 0;
+    match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+        0 | -1 => None,
+        _ => Some(status_code(ExitStatus::from_raw(status))),
+    }
+}
+
+/// Reads a line from `fd` one byte at a time, so that nothing past the
+/// newline is consumed: the commands of a script read from standard input
+/// may read the rest of it themselves. Returns `None` at end of input.
+pub fn read_line_unbuffered(fd: RawFd) -> Option<String> {
+    let mut line = vec![];
+
+    while let Ok(Some(byte)) = read_byte(fd, None) {
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+
+    if line.is_empty() {
+        None
     } else {
-        options.write(true).truncate(true);
+        Some(String::from_utf8_lossy(&line).into_owned())
     }
-    let mut file = options.open(path).unwrap();
-    file.write_all(contents).unwrap();
 }
 
-pub fn find_executable_on_path(cmd_name: &str) -> Option<PathBuf> {
-    let path_var = env::var_os("PATH")?;
+/// Reads a single byte from `fd`, waiting at most until `deadline`.
+/// Returns `Ok(None)` at end of input, and a `TimedOut` error once the
+/// deadline has passed.
+pub fn read_byte(fd: RawFd, deadline: Option<Instant>) -> io::Result<Option<u8>> {
+    if let Some(deadline) = deadline
+        && !wait_readable(fd, deadline.saturating_duration_since(Instant::now()))?
+    {
+        return Err(io::ErrorKind::TimedOut.into());
+    }
 
-    for path in env::split_paths(&path_var) {
-        let full_path = path.join(cmd_name);
+    let mut byte = 0u8;
+    loop {
+        let count = unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) };
+        match count {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
 
-        if let Ok(metadata) = full_path.metadata() {
-            if metadata.is_file() && (metadata.permissions().mode() & 0o111 != 0) {
-                return Some(full_path);
+/// Whether `fd` has input (or end of input) to read within `timeout`.
+pub fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+    loop {
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            -1 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
             }
+            ready => return Ok(ready > 0),
         }
     }
-    None
 }
 
-pub fn run_cmd(program: &str, args: &[&str]) -> Output {
-    Command::new(program).args(args).output().unwrap()
+pub fn is_terminal(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Stops the terminal on `fd` from echoing what is typed, returning the
+/// settings to restore afterwards, or `None` when `fd` is no terminal.
+pub fn disable_echo(fd: RawFd) -> Option<libc::termios> {
+    let mut settings: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut settings) } != 0 {
+        return None;
+    }
+    let saved = settings;
+    settings.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &settings) };
+    Some(saved)
+}
+
+pub fn restore_terminal(fd: RawFd, settings: &libc::termios) {
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, settings) };
+}
+
+/// Whether `text` matches the glob `pattern`, in which a backslash makes
+/// the next character match literally.
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let (Ok(pattern), Ok(text)) = (CString::new(pattern), CString::new(text)) else {
+        return false;
+    };
+    unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
+
+/// Matches `text` against the POSIX extended regular expression `pattern`.
+/// Returns the matched text and that of each group, empty for groups that
+/// did not take part, or `None` when there is no match.
+pub fn regex_match(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+    let invalid = || format!("{pattern}: invalid regular expression");
+    let c_pattern = CString::new(pattern).map_err(|_| invalid())?;
+    let Ok(c_text) = CString::new(text) else {
+        return Ok(None);
+    };
+
+    let mut regex: libc::regex_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED) } != 0 {
+        return Err(invalid());
+    }
+    let group_count = regex_group_count(pattern);
+    let mut matches = vec![
+        libc::regmatch_t {
+            rm_so: -1,
+            rm_eo: -1
+        };
+        group_count + 1
+    ];
+    let status = unsafe {
+        libc::regexec(
+            &regex,
+            c_text.as_ptr(),
+            matches.len(),
+            matches.as_mut_ptr(),
+            0,
+        )
+    };
+    unsafe { libc::regfree(&mut regex) };
+
+    if status != 0 {
+        return Ok(None);
+    }
+    let groups = matches
+        .iter()
+        .map(
+            |group| match (usize::try_from(group.rm_so), usize::try_from(group.rm_eo)) {
+                (Ok(start), Ok(end)) => {
+                    String::from_utf8_lossy(&text.as_bytes()[start..end]).into_owned()
+                }
+                _ => String::new(),
+            },
+        )
+        .collect();
+    Ok(Some(groups))
+}
+
+/// The number of groups of a regular expression: its `(` outside of
+/// bracket expressions and not escaped.
+fn regex_group_count(pattern: &str) -> usize {
+    let mut count = 0;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => count += 1,
+            '[' => {
+                // A `]` right after `[` or `[^` is part of the expression.
+                let mut first = true;
+                for c in chars.by_ref() {
+                    if c == ']' && !first {
+                        break;
+                    }
+                    first = first && c == '^';
+                }
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+pub fn home_dir_of(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    lookup_passwd_home(|pwd, buf, buflen, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), pwd, buf, buflen, result)
+    })
+}
+
+pub fn current_user_home() -> Option<String> {
+    lookup_passwd_home(|pwd, buf, buflen, result| unsafe {
+        libc::getpwuid_r(libc::getuid(), pwd, buf, buflen, result)
+    })
+}
+
+fn lookup_passwd_home<F>(lookup: F) -> Option<String>
+where
+    F: Fn(*mut libc::passwd, *mut libc::c_char, usize, *mut *mut libc::passwd) -> libc::c_int,
+{
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+    if lookup(&mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) != 0 || result.is_null() {
+        return None;
+    }
+    let home = unsafe { CStr::from_ptr(pwd.pw_dir) };
+    Some(home.to_string_lossy().into_owned())
 }
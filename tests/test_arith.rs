@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod test_arith {
+    use codecrafters_shell::arith::evaluate;
+    use codecrafters_shell::state::ShellState;
+
+    #[test]
+    fn test_precedence() {
+        let mut state = ShellState::new();
+        assert_eq!(evaluate("1 + 2 * 3", &mut state), Ok(7));
+        assert_eq!(evaluate("(1 + 2) * 3", &mut state), Ok(9));
+        assert_eq!(evaluate("2 ** 3 ** 2", &mut state), Ok(512));
+        assert_eq!(evaluate("-2 ** 2", &mut state), Ok(4));
+        assert_eq!(evaluate("1 < 2 && 3 > 4 || !0", &mut state), Ok(1));
+        assert_eq!(evaluate("5 & 3 | 8 ^ 1", &mut state), Ok(9));
+    }
+
+    #[test]
+    fn test_bases() {
+        let mut state = ShellState::new();
+        assert_eq!(
+            evaluate("16#ff + 0x10 + 010 + 2#11", &mut state),
+            Ok(255 + 16 + 8 + 3)
+        );
+        assert_eq!(evaluate("36#Z + 64#@", &mut state), Ok(35 + 62));
+        assert!(evaluate("2#12", &mut state).is_err());
+    }
+
+    #[test]
+    fn test_assignments() {
+        let mut state = ShellState::new();
+        assert_eq!(evaluate("x = 5, x += 2, x *= 3", &mut state), Ok(21));
+        assert_eq!(state.get_var("x"), Some("21"));
+        assert_eq!(evaluate("x++ + ++x", &mut state), Ok(21 + 23));
+        assert_eq!(evaluate("y = x > 10 ? x-- : 0", &mut state), Ok(23));
+        assert_eq!(state.get_var("x"), Some("22"));
+    }
+
+    #[test]
+    fn test_variables_as_expressions() {
+        let mut state = ShellState::new();
+        state.set_var("a", "b + 1");
+        state.set_var("b", "2");
+        assert_eq!(evaluate("a * 2", &mut state), Ok(6));
+        assert_eq!(evaluate("unset_variable + 1", &mut state), Ok(1));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let mut state = ShellState::new();
+        assert_eq!(evaluate("0 && (z = 1)", &mut state), Ok(0));
+        assert_eq!(evaluate("1 ? 2 : (z = 3)", &mut state), Ok(2));
+        assert_eq!(state.get_var("z"), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut state = ShellState::new();
+        assert!(evaluate("1 / 0", &mut state).is_err());
+        assert!(evaluate("2 ** -1", &mut state).is_err());
+        assert!(evaluate("1 +", &mut state).is_err());
+        assert!(evaluate("(1", &mut state).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+#[cfg(test)]
+mod test_expand {
+    use codecrafters_shell::expand::{expand_arithmetic, expand_to_string, expand_word};
+    use codecrafters_shell::parse::run_parser;
+    use codecrafters_shell::state::ShellState;
+
+    #[test]
+    fn test_tilde_user() {
+        let mut state = ShellState::new();
+        assert_eq!(
+            expand_to_string("~root/notes.txt", &mut state).unwrap(),
+            "/root/notes.txt"
+        );
+        assert_eq!(
+            expand_to_string("~nosuchuser/x", &mut state).unwrap(),
+            "~nosuchuser/x"
+        );
+    }
+
+    #[test]
+    fn test_tilde_assignment() {
+        let mut state = ShellState::new();
+        assert_eq!(
+            expand_to_string("PATH=~root/bin:~root/.local/bin", &mut state).unwrap(),
+            "PATH=/root/bin:/root/.local/bin"
+        );
+        assert_eq!(expand_to_string("a~root", &mut state).unwrap(), "a~root");
+    }
+
+    #[test]
+    fn test_quoted_tilde() {
+        let mut state = ShellState::new();
+        let results = run_parser("ls '~root' \"~root\"/x ~root").unwrap();
+        println!("Got: {:#?}", results);
+        let (cmd, args) = results[0].get_cmd_args();
+        assert_eq!(cmd, "ls");
+        let expanded: Vec<String> = args
+            .iter()
+            .map(|arg| expand_to_string(arg, &mut state).unwrap())
+            .collect();
+        assert_eq!(expanded, vec!["~root", "~root/x", "/root"]);
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let mut state = ShellState::new();
+        assert_eq!(
+            expand_word("$(echo a  b)", &mut state).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            expand_word("\"$(printf 'a  b')\"", &mut state).unwrap(),
+            vec!["a  b"]
+        );
+        assert_eq!(
+            expand_word("x$(echo $(echo nested))y", &mut state).unwrap(),
+            vec!["xnestedy"]
+        );
+        assert_eq!(
+            expand_word("`echo old style`", &mut state).unwrap(),
+            vec!["old", "style"]
+        );
+        assert_eq!(
+            expand_word("\"$(printf 'a\\n\\n')\"", &mut state).unwrap(),
+            vec!["a"]
+        );
+        assert_eq!(
+            expand_word("$(true)", &mut state).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_parse_substitution_as_one_word() {
+        let results = run_parser("echo $(ls | head -n 1; echo ')') > out.txt").unwrap();
+        println!("Got: {:#?}", results);
+        let (_, args) = results[0].get_cmd_args();
+        assert_eq!(args, vec!["$(ls | head -n 1; echo ')')"]);
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_arithmetic_expansion() {
+        let mut state = ShellState::new();
+        state.set_var("x", "4");
+        assert_eq!(
+            expand_word("$((x * 2 + 1))", &mut state).unwrap(),
+            vec!["9"]
+        );
+        assert_eq!(
+            expand_word("\"$(( ${x} << 2 ))\"", &mut state).unwrap(),
+            vec!["16"]
+        );
+        assert_eq!(expand_word("$((x++))$x", &mut state).unwrap(), vec!["45"]);
+        assert_eq!(
+            expand_arithmetic("\"$x\" + $(echo \"1\")", &mut state).unwrap(),
+            "5 + 1"
+        );
+
+        let error = expand_word("a$((1/0))$(echo never >&2)", &mut state).unwrap_err();
+        assert_eq!(error.status(), 1);
+        assert!(error.to_string().starts_with("1/0: division by 0"));
+    }
+
+    #[test]
+    fn test_process_substitution() {
+        let mut state = ShellState::new();
+        let fields = expand_word("<(echo hi)", &mut state).unwrap();
+        assert!(fields[0].starts_with("/dev/fd/"));
+        assert_eq!(std::fs::read_to_string(&fields[0]).unwrap(), "hi\n");
+        assert_eq!(
+            expand_word("$(cat <(echo a) <(echo b))", &mut state).unwrap(),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_ifs_splitting() {
+        let mut state = ShellState::new();
+        state.set_var("v", "  a  b\tc  ");
+        assert_eq!(expand_word("$v", &mut state).unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(
+            expand_word("\"$v\"", &mut state).unwrap(),
+            vec!["  a  b\tc  "]
+        );
+
+        state.set_var("IFS", ":");
+        state.set_var("v", ":a::b c:");
+        assert_eq!(
+            expand_word("$v", &mut state).unwrap(),
+            vec!["", "a", "", "b c"]
+        );
+
+        state.set_var("IFS", " :");
+        state.set_var("v", " a : b: :c ");
+        assert_eq!(
+            expand_word("x$v", &mut state).unwrap(),
+            vec!["x", "a", "b", "", "c"]
+        );
+
+        state.set_var("IFS", "");
+        assert_eq!(expand_word("$v", &mut state).unwrap(), vec![" a : b: :c "]);
+        state.set_var("v", "");
+        assert_eq!(expand_word("$v", &mut state).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            expand_word("$v$v", &mut state).unwrap(),
+            Vec::<String>::new()
+        );
+
+        state.unset_var("IFS");
+        state.set_var("v", "");
+        assert_eq!(expand_word("$v", &mut state).unwrap(), Vec::<String>::new());
+        assert_eq!(expand_word("\"$v\"", &mut state).unwrap(), vec![""]);
+    }
+
+    #[test]
+    fn test_positional_parameters() {
+        let mut state = ShellState::new();
+        state.positional = vec!["a b".to_string(), "".to_string(), "c".to_string()];
+        assert_eq!(
+            expand_word("\"$@\"", &mut state).unwrap(),
+            vec!["a b", "", "c"]
+        );
+        assert_eq!(
+            expand_word("\"x$@y\"", &mut state).unwrap(),
+            vec!["xa b", "", "cy"]
+        );
+        assert_eq!(expand_word("$@", &mut state).unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(expand_word("\"$*\"", &mut state).unwrap(), vec!["a b  c"]);
+        assert_eq!(
+            expand_word("\"${@}\"", &mut state).unwrap(),
+            vec!["a b", "", "c"]
+        );
+        assert_eq!(
+            expand_word("${*}", &mut state).unwrap(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(expand_word("\"${*}\"", &mut state).unwrap(), vec!["a b  c"]);
+        assert_eq!(
+            expand_word("\"$# $1 ${3}\"", &mut state).unwrap(),
+            vec!["3 a b c"]
+        );
+
+        state.positional.clear();
+        assert_eq!(
+            expand_word("\"$@\"", &mut state).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            expand_word("\"${@}\"", &mut state).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(expand_word("\"$*\"", &mut state).unwrap(), vec![""]);
+    }
+
+    #[test]
+    fn test_ansi_c_quoting() {
+        let mut state = ShellState::new();
+        assert_eq!(
+            expand_word(r#"$'a b\tc\'d\\'"$'e'""#, &mut state).unwrap(),
+            vec!["a b\tc'd\\$'e'"]
+        );
+        assert_eq!(
+            expand_word(r"x$'\101\x42\E\0c'", &mut state).unwrap(),
+            vec!["xAB\x1b"]
+        );
+        let results = run_parser(r"echo $'it\'s' $(echo $'a\')')").unwrap();
+        let (_, args) = results[0].get_cmd_args();
+        assert_eq!(args, [r"$'it\'s'", r"$(echo $'a\')')"]);
+    }
+}
@@ -0,0 +1,312 @@
+#[cfg(test)]
+mod test_interpret {
+    use std::io::Write;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use codecrafters_shell::builtins::{Builtin, BuiltinIo};
+    use codecrafters_shell::commands::run_builtin;
+    use codecrafters_shell::interpret::run_script;
+    use codecrafters_shell::state::ShellState;
+
+    /// The tests change the process' working directory and standard file
+    /// descriptors, so they take turns rather than run in parallel.
+    static PROCESS: Mutex<()> = Mutex::new(());
+
+    fn lock_process() -> MutexGuard<'static, ()> {
+        PROCESS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[test]
+    fn test_run_script_status() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        let script = "f() {\n  local x=$1\n  return $x\n}\nf 4\n";
+        assert_eq!(run_script(script, &mut state), 4);
+
+        let mut state = ShellState::new();
+        assert_eq!(run_script("exit 3\nexit 5\n", &mut state), 3);
+        assert_eq!(state.get_var("x"), None);
+
+        let mut state = ShellState::new();
+        assert_eq!(run_script("x=$(false)", &mut state), 1);
+        assert_eq!(run_script("x=$(exit 4) y=$(true)", &mut state), 0);
+        assert_eq!(run_script("false; x=1", &mut state), 0);
+        run_script("a=$(echo $(exit 3) $?)\nb=$?", &mut state);
+        assert_eq!(state.get_var("a"), Some("3"));
+        assert_eq!(state.get_var("b"), Some("0"));
+    }
+
+    #[test]
+    fn test_exec() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        assert_eq!(run_script("FOO=1 exec 2>&2; echo $FOO", &mut state), 0);
+        assert_eq!(state.get_var("FOO"), Some("1"));
+
+        let mut state = ShellState::new();
+        let script = "exec /nonexistent/program; x=still";
+        assert_eq!(run_script(script, &mut state), 127);
+        assert_eq!(state.get_var("x"), None);
+
+        let mut state = ShellState::new();
+        state.options.interactive = true;
+        assert_eq!(run_script(script, &mut state), 0);
+        assert_eq!(state.get_var("x"), Some("still"));
+    }
+
+    #[test]
+    fn test_run_script_loops() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        let script = "n=0\nfor i in 1 2 3 4; do\n  case $i in\n    3) break;;\n  esac\n  n=$((n + i))\ndone\n";
+        run_script(script, &mut state);
+        assert_eq!(state.get_var("n"), Some("3"));
+
+        let script = "true && a=1 || a=2\nfalse && b=1 || b=2\nfalse || false &&\n  c=1\nfor i in 1 2; do\n  [[ $i == 1 ]] && continue\n  d=$i\ndone\n";
+        assert_eq!(run_script(script, &mut state), 0);
+        assert_eq!(state.get_var("a"), Some("1"));
+        assert_eq!(state.get_var("b"), Some("2"));
+        assert_eq!(state.get_var("c"), None);
+        assert_eq!(state.get_var("d"), Some("2"));
+        assert_eq!(run_script("false || (exit 4) && true", &mut state), 4);
+    }
+
+    #[test]
+    fn test_export_and_unset() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        run_script("export GREETING=\"a b\"\nLOCAL=1\n", &mut state);
+        let exported = state.exported_vars();
+        assert!(exported.contains(&(String::from("GREETING"), String::from("a b"))));
+        assert!(!exported.iter().any(|(name, _)| name == "LOCAL"));
+
+        run_script("unset GREETING LOCAL", &mut state);
+        assert_eq!(state.get_var("GREETING"), None);
+        assert_eq!(state.get_var("LOCAL"), None);
+    }
+
+    #[test]
+    fn test_source() {
+        let _process = lock_process();
+        let path = std::env::temp_dir().join("test_source_lib.sh");
+        std::fs::write(
+            &path,
+            "SOURCED=\"$1\"\nsourced_fn() { return 2; }\nreturn 5\nSOURCED=no\n",
+        )
+        .unwrap();
+
+        let mut state = ShellState::new();
+        state.positional = vec![String::from("outer")];
+        let script = format!("source {} inner", path.display());
+        assert_eq!(run_script(&script, &mut state), 5);
+        assert_eq!(state.get_var("SOURCED"), Some("inner"));
+        assert_eq!(state.positional, vec![String::from("outer")]);
+        assert_eq!(run_script("sourced_fn", &mut state), 2);
+    }
+
+    #[test]
+    fn test_eval() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        run_script("code='y=$((x + 1))'\nx=1\neval \"$code\"\n", &mut state);
+        assert_eq!(state.get_var("y"), Some("2"));
+        assert_eq!(run_script("eval 'true; false'", &mut state), 1);
+
+        // What `printf %q` quotes, `eval` reads back unchanged.
+        let value = "a\nb\tc\x1bd'e\\f g\x01";
+        state.set_var("value", value);
+        run_script("eval \"copy=$(printf %q \"$value\")\"", &mut state);
+        assert_eq!(state.get_var("copy"), Some(value));
+    }
+
+    #[test]
+    fn test_read_at_end_of_pipeline() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        run_script("printf ' a  b\\ c  d \\n' | read x y", &mut state);
+        assert_eq!(state.get_var("x"), Some("a"));
+        assert_eq!(state.get_var("y"), Some("b c  d"));
+
+        run_script("printf 'a\\\\b\\n' | read -r raw", &mut state);
+        assert_eq!(state.get_var("raw"), Some("a\\b"));
+
+        run_script("printf '1:2::3' | IFS=: read -a parts", &mut state);
+        let parts = state.get_array("parts").unwrap();
+        assert_eq!(parts, ["1", "2", "", "3"]);
+        assert_eq!(state.get_var("IFS"), None);
+
+        assert_eq!(
+            run_script("printf 'abcdef' | read -n 4 -d c chars", &mut state),
+            0
+        );
+        assert_eq!(state.get_var("chars"), Some("ab"));
+        assert_eq!(run_script("printf 'line' | read", &mut state), 1);
+        assert_eq!(state.get_var("REPLY"), Some("line"));
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        assert_eq!(run_script("[ 2 -gt 1 -a abc != abd ]", &mut state), 0);
+        assert_eq!(run_script("test ! -d /", &mut state), 1);
+        assert_eq!(run_script("[ 1 -lt x ]", &mut state), 2);
+
+        run_script("v='a b'", &mut state);
+        assert_eq!(run_script("[[ $v == a* && ! -z $v ]]", &mut state), 0);
+        assert_eq!(run_script("[[ $v == \"a*\" ]]", &mut state), 1);
+        assert_eq!(run_script("[[ x < y || $(exit 1) ]]", &mut state), 0);
+        assert_eq!(run_script("[[ (1 -eq 2) || (a == a) ]]", &mut state), 0);
+        assert_eq!(run_script("[[ ! (1 -eq 1) ]]", &mut state), 1);
+
+        assert_eq!(
+            run_script("[[ v2.10 =~ ^v([0-9]+)\\.([0-9]+)$ ]]", &mut state),
+            0
+        );
+        let groups = state.get_array("BASH_REMATCH").unwrap();
+        assert_eq!(groups, ["v2.10", "2", "10"]);
+        assert_eq!(run_script("[[ abc =~ \"a.c\" ]]", &mut state), 1);
+    }
+
+    #[test]
+    fn test_builtin_io() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        state.history = vec![String::from("echo a"), String::from("ls")];
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let mut io = BuiltinIo {
+            stdout: Box::new(&mut stdout),
+            stderr: Box::new(&mut stderr),
+        };
+        let args = [String::from("echo"), String::from("nosuch")];
+        assert_eq!(
+            run_builtin("type", &args, &[], &mut state, &mut io),
+            Some(1)
+        );
+        assert_eq!(
+            run_builtin("history", &[], &[], &mut state, &mut io),
+            Some(0)
+        );
+        drop(io);
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "echo is a shell builtin\n1  echo a\n2  ls\n"
+        );
+        assert_eq!(
+            String::from_utf8(stderr).unwrap(),
+            "type: nosuch: not found\n"
+        );
+    }
+
+    #[test]
+    fn test_builtin_errors() {
+        let _process = lock_process();
+        let mut state = ShellState::new();
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let mut io = BuiltinIo {
+            stdout: Box::new(&mut stdout),
+            stderr: Box::new(&mut stderr),
+        };
+        let args = [String::from("x")];
+        assert_eq!(
+            run_builtin("local", &args, &[], &mut state, &mut io),
+            Some(1)
+        );
+        assert_eq!(
+            run_builtin("exit", &args, &[], &mut state, &mut io),
+            Some(2)
+        );
+        assert_eq!(
+            run_builtin("source", &[], &[], &mut state, &mut io),
+            Some(2)
+        );
+        drop(io);
+        assert!(stdout.is_empty());
+        assert_eq!(
+            String::from_utf8(stderr).unwrap(),
+            "local: can only be used in a function\n\
+             exit: x: numeric argument required\n\
+             source: filename argument required\n"
+        );
+    }
+
+    #[test]
+    fn test_redirected_and_piped_builtins() {
+        let _process = lock_process();
+        let path = std::env::temp_dir().join("test_redirected_builtins.txt");
+        let mut state = ShellState::new();
+        let script = format!(
+            "type echo > {0}\nprintf '%s\\n' a b | read x\ntype test | cat >> {0}",
+            path.display()
+        );
+        run_script(&script, &mut state);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "echo is a shell builtin\ntest is a shell builtin\n"
+        );
+        assert_eq!(state.get_var("x"), Some("a"));
+
+        let script = format!(
+            "read first < {0}\nwhile read line; do n=$((n + 1)); done 3<&0 < {0}",
+            path.display()
+        );
+        run_script(&script, &mut state);
+        assert_eq!(state.get_var("first"), Some("echo is a shell builtin"));
+        assert_eq!(state.get_var("n"), Some("2"));
+        assert_eq!(run_script("cat < /nonexistent/file", &mut state), 1);
+
+        let script = "x=a\nwhile read line; do lines=\"$lines[$line]\"; done <<EOF\n$x \\$x \"q\"\n\tEOF\nEOF\nread raw <<-'END'\n\t$x\n\tEND\n";
+        run_script(script, &mut state);
+        assert_eq!(state.get_var("lines"), Some("[a $x \"q\"][EOF]"));
+        assert_eq!(state.get_var("raw"), Some("$x"));
+    }
+
+    /// Counts its arguments into `COUNT`, and shows the `GREETING` it got.
+    struct CountArgs;
+
+    impl Builtin for CountArgs {
+        fn name(&self) -> &str {
+            "count-args"
+        }
+
+        fn help(&self) -> &str {
+            "count-args [arg ...]\n    Count the arguments."
+        }
+
+        fn run(
+            &self,
+            args: &[String],
+            env: &[(String, String)],
+            io: &mut BuiltinIo,
+            state: &mut ShellState,
+        ) -> i32 {
+            state.set_var("COUNT", &args.len().to_string());
+            let greeting = state.get_var("GREETING").unwrap_or_default();
+            let _ = writeln!(io.stdout, "{greeting} {}", env.len());
+            0
+        }
+    }
+
+    #[test]
+    fn test_registered_builtin() {
+        let _process = lock_process();
+        let path = std::env::temp_dir().join("test_registered_builtin.txt");
+        let mut state = ShellState::new();
+        assert!(state.builtins.register(CountArgs).is_none());
+
+        let script = format!(
+            "GREETING=hi count-args a 'b c' > {0}\ntype count-args >> {0}\nhelp count-args >> {0}",
+            path.display()
+        );
+        assert_eq!(run_script(&script, &mut state), 0);
+        assert_eq!(state.get_var("COUNT"), Some("2"));
+        assert_eq!(state.get_var("GREETING"), None);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "hi 1\ncount-args is a shell builtin\ncount-args: count-args [arg ...]\n    Count the arguments.\n"
+        );
+
+        state.builtins.remove("count-args");
+        assert_eq!(run_script("type count-args 2> /dev/null", &mut state), 1);
+    }
+}
@@ -0,0 +1,152 @@
+#[cfg(test)]
+mod test_lexer {
+    use codecrafters_shell::lexer::{TokenKind, tokenize};
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    fn word(word: &str) -> TokenKind {
+        TokenKind::Word(String::from(word))
+    }
+
+    #[test]
+    fn test_io_numbers() {
+        assert_eq!(
+            kinds("echo 12 a1>b 2>&1"),
+            vec![
+                word("echo"),
+                word("12"),
+                word("a1"),
+                TokenKind::Operator(">"),
+                word("b"),
+                TokenKind::IoNumber(2),
+                TokenKind::Operator(">&"),
+                word("1"),
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(
+            kinds("echo 2 >x"),
+            vec![
+                word("echo"),
+                word("2"),
+                TokenKind::Operator(">"),
+                word("x"),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_io_number_needs_adjacent_operator() {
+        assert_eq!(
+            kinds("cat 12>x 12 >x 0<in"),
+            vec![
+                word("cat"),
+                TokenKind::IoNumber(12),
+                TokenKind::Operator(">"),
+                word("x"),
+                word("12"),
+                TokenKind::Operator(">"),
+                word("x"),
+                TokenKind::IoNumber(0),
+                TokenKind::Operator("<"),
+                word("in"),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(
+            kinds("a;;&b|c>>d\n"),
+            vec![
+                word("a"),
+                TokenKind::Operator(";;&"),
+                word("b"),
+                TokenKind::Operator("|"),
+                word("c"),
+                TokenKind::Operator(">>"),
+                word("d"),
+                TokenKind::Newline,
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(
+            kinds("cat <(ls) \"a;b\" $(x | y)"),
+            vec![
+                word("cat"),
+                word("<(ls)"),
+                word("\"a;b\""),
+                word("$(x | y)"),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_here_docs() {
+        let here_doc = |delimiter: &str, body: &str| TokenKind::HereDoc {
+            delimiter: String::from(delimiter),
+            body: String::from(body),
+        };
+        assert_eq!(
+            kinds("cat <<A <<-'B'; echo\na\nA\n\tb\n\tB\nls\n"),
+            vec![
+                word("cat"),
+                TokenKind::Operator("<<"),
+                here_doc("A", "a\n"),
+                TokenKind::Operator("<<-"),
+                here_doc("'B'", "b\n"),
+                TokenKind::Operator(";"),
+                word("echo"),
+                TokenKind::Newline,
+                word("ls"),
+                TokenKind::Newline,
+                TokenKind::Eof
+            ]
+        );
+        assert!(tokenize("cat <<EOF\nabc\n").is_err());
+    }
+
+    #[test]
+    fn test_reserved_words() {
+        assert_eq!(
+            kinds("if echo then; then fi; fi"),
+            vec![
+                TokenKind::Reserved("if"),
+                word("echo"),
+                word("then"),
+                TokenKind::Operator(";"),
+                TokenKind::Reserved("then"),
+                TokenKind::Reserved("fi"),
+                TokenKind::Operator(";"),
+                TokenKind::Reserved("fi"),
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(
+            kinds("for ((i=0; i<2; i++)) [[ a ]]"),
+            vec![
+                TokenKind::Reserved("for"),
+                TokenKind::Arithmetic(String::from("i=0; i<2; i++")),
+                TokenKind::Conditional(String::from(" a ")),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = tokenize("ls  -l # x\\\n").unwrap();
+        let spans: Vec<_> = tokens.iter().map(|token| token.span.clone()).collect();
+        assert_eq!(spans, vec![0..2, 4..6, 11..12, 12..12]);
+        assert_eq!(tokens[2].kind, TokenKind::Newline);
+    }
+}
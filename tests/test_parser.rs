@@ -1,13 +1,23 @@
 #[cfg(test)]
 mod test_parser {
-    use codecrafters_shell::enums::{Command, RedirectMode, WriteFileMode};
-    use codecrafters_shell::parse::run_parser;
+    use codecrafters_shell::enums::{
+        CaseItem, CaseTerminator, Command, RedirectMode, WriteFileMode,
+    };
+    use codecrafters_shell::error::{SyntaxError, SyntaxErrorKind};
+    use codecrafters_shell::parse::{is_incomplete, run_parser};
+
+    fn program(cmd: &str, args: &[&str]) -> Command {
+        Command::Program {
+            cmd: String::from(cmd),
+            args: args.iter().map(|arg| String::from(*arg)).collect(),
+        }
+    }
 
     #[test]
     fn test_parser_1() {
         let input_string = String::from("echo '123' 2>> file.txt");
         println!("Parsing {input_string}");
-        let results = run_parser(&input_string);
+        let results = run_parser(&input_string).unwrap();
         println!("Got: {:#?}", results);
         assert_eq!(
             results,
@@ -29,7 +39,7 @@ mod test_parser {
     fn test_parser_2() {
         let input_string = String::from("cat file.txt | head 1 1> file2.txt");
         println!("Parsing {input_string}");
-        let results = run_parser(&input_string);
+        let results = run_parser(&input_string).unwrap();
         println!("Got: {:#?}", results);
         assert_eq!(
             results,
@@ -59,7 +69,7 @@ mod test_parser {
     fn test_parser_3() {
         let input_string = String::from("tail -f tests/testdata.txt | head -n 5");
         println!("Parsing {input_string}");
-        let results = run_parser(&input_string);
+        let results = run_parser(&input_string).unwrap();
         println!("Got: {:#?}", results);
         assert_eq!(
             results,
@@ -82,7 +92,7 @@ mod test_parser {
     fn test_parser_4() {
         let input_string = String::from("ls -1 nonexistent 2>> /tmp/dog/cow.md");
         println!("Parsing {input_string}");
-        let results = run_parser(&input_string);
+        let results = run_parser(&input_string).unwrap();
         println!("Got: {:#?}", results);
         assert_eq!(
             results,
@@ -104,7 +114,7 @@ mod test_parser {
     fn test_parser_5() {
         let input_string = String::from("ls | type exit");
         println!("Parsing {input_string}");
-        let results = run_parser(&input_string);
+        let results = run_parser(&input_string).unwrap();
         println!("Got: {:#?}", results);
         assert_eq!(
             results,
@@ -122,4 +132,482 @@ mod test_parser {
             }]
         )
     }
+
+    #[test]
+    fn test_parser_6() {
+        let input_string = String::from(
+            "if true; then echo yes; elif false\nthen echo no; else echo maybe; fi > out.txt",
+        );
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![
+                Command::If {
+                    branches: vec![
+                        (vec![program("true", &[])], vec![program("echo", &["yes"])]),
+                        (vec![program("false", &[])], vec![program("echo", &["no"])])
+                    ],
+                    else_body: Some(vec![program("echo", &["maybe"])])
+                },
+                Command::RedirectTo {
+                    path: String::from("out.txt"),
+                    redirect_mode: RedirectMode::StdOut,
+                    write_mode: WriteFileMode::OverWrite
+                }
+            ]
+        )
+    }
+
+    #[test]
+    fn test_parser_7() {
+        assert!(is_incomplete("if true; then\n"));
+        assert!(is_incomplete("if true; then if false; then :; fi\n"));
+        assert!(!is_incomplete("if true; then :; fi\n"));
+        assert!(!is_incomplete("echo if then\n"));
+    }
+
+    #[test]
+    fn test_parser_8() {
+        let input_string = String::from(
+            "for x in a $b; do while true; do break 2; done; done\nfor ((i=0; i<3; i++)) do echo $i; done",
+        );
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![
+                Command::For {
+                    variable: String::from("x"),
+                    words: Some(vec![String::from("a"), String::from("$b")]),
+                    body: vec![Command::While {
+                        condition: vec![program("true", &[])],
+                        body: vec![program("break", &["2"])]
+                    }]
+                },
+                Command::ArithmeticFor {
+                    init: String::from("i=0"),
+                    condition: String::from("i<3"),
+                    step: String::from("i++"),
+                    body: vec![program("echo", &["$i"])]
+                }
+            ]
+        );
+        assert!(is_incomplete("until false; do\n"));
+        assert!(!is_incomplete("echo done\n"));
+    }
+
+    #[test]
+    fn test_parser_9() {
+        let input_string =
+            String::from("case $x in\n(a|'b*') echo one;& c) ;;& *) echo two; echo three\nesac");
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![Command::Case {
+                word: String::from("$x"),
+                items: vec![
+                    CaseItem {
+                        patterns: vec![String::from("a"), String::from("'b*'")],
+                        body: vec![program("echo", &["one"])],
+                        terminator: CaseTerminator::FallThrough
+                    },
+                    CaseItem {
+                        patterns: vec![String::from("c")],
+                        body: vec![],
+                        terminator: CaseTerminator::Continue
+                    },
+                    CaseItem {
+                        patterns: vec![String::from("*")],
+                        body: vec![program("echo", &["two"]), program("echo", &["three"])],
+                        terminator: CaseTerminator::Break
+                    }
+                ]
+            }]
+        );
+        assert!(is_incomplete("case x in\n"));
+        assert!(!is_incomplete("case x in x) echo esac;; esac\n"));
+    }
+
+    #[test]
+    fn test_parser_10() {
+        let input_string =
+            String::from("greet () { local x=$1; return 3; }\nfunction bye\n{ echo bye; }");
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![
+                Command::FunctionDef {
+                    name: String::from("greet"),
+                    body: Box::new(Command::Group {
+                        body: vec![program("local", &["x=$1"]), program("return", &["3"])]
+                    }),
+                    redirects: vec![]
+                },
+                Command::FunctionDef {
+                    name: String::from("bye"),
+                    body: Box::new(Command::Group {
+                        body: vec![program("echo", &["bye"])]
+                    }),
+                    redirects: vec![]
+                }
+            ]
+        );
+        assert!(is_incomplete("f() {\n"));
+        assert!(!is_incomplete("echo {\n"));
+    }
+
+    #[test]
+    fn test_parser_11() {
+        let input_string = String::from(
+            "(cd /tmp; ls) | { read x; echo $x; } > out.txt; ls | while read f; do echo $f; done",
+        );
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![
+                Command::Pipe {
+                    programs: vec![
+                        Box::new(Command::Subshell {
+                            body: vec![program("cd", &["/tmp"]), program("ls", &[])]
+                        }),
+                        Box::new(Command::Group {
+                            body: vec![program("read", &["x"]), program("echo", &["$x"])]
+                        })
+                    ]
+                },
+                Command::RedirectTo {
+                    path: String::from("out.txt"),
+                    redirect_mode: RedirectMode::StdOut,
+                    write_mode: WriteFileMode::OverWrite
+                },
+                Command::Pipe {
+                    programs: vec![
+                        Box::new(program("ls", &[])),
+                        Box::new(Command::While {
+                            condition: vec![program("read", &["f"])],
+                            body: vec![program("echo", &["$f"])]
+                        })
+                    ]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_12() {
+        let input_string = String::from("#!/bin/shell\necho a#b # if this were code\n# fi");
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![Command::Program {
+                cmd: String::from("echo"),
+                args: vec![String::from("a#b")]
+            }]
+        );
+        assert!(!is_incomplete("echo # if\n"));
+    }
+
+    #[test]
+    fn test_parser_13() {
+        let input_string = String::from("exec 3>log 2>&1");
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![
+                Command::Program {
+                    cmd: String::from("exec"),
+                    args: vec![]
+                },
+                Command::RedirectTo {
+                    path: String::from("log"),
+                    redirect_mode: RedirectMode::Fd(3),
+                    write_mode: WriteFileMode::OverWrite
+                },
+                Command::RedirectTo {
+                    path: String::from("&1"),
+                    redirect_mode: RedirectMode::StdErr,
+                    write_mode: WriteFileMode::OverWrite
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_14() {
+        let input_string = String::from("[[ $x == \"a b\"* && -f 'a]]' ]] > out.txt");
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string).unwrap();
+        println!("Got: {:#?}", results);
+        assert_eq!(
+            results,
+            vec![
+                Command::Conditional {
+                    words: vec![
+                        String::from("$x"),
+                        String::from("=="),
+                        String::from("\"a b\"*"),
+                        String::from("&&"),
+                        String::from("-f"),
+                        String::from("'a]]'"),
+                    ]
+                },
+                Command::RedirectTo {
+                    path: String::from("out.txt"),
+                    redirect_mode: RedirectMode::StdOut,
+                    write_mode: WriteFileMode::OverWrite
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_15() {
+        let input_string = String::from("while true; do\n  echo x\n");
+        println!("Parsing {input_string}");
+        let results = run_parser(&input_string);
+        println!("Got: {:#?}", results);
+        let error = results.unwrap_err();
+        assert_eq!(error.kind, SyntaxErrorKind::UnexpectedEof);
+        assert!(error.is_incomplete());
+        assert!(is_incomplete("echo \"a\nb"));
+        assert!(is_incomplete("ls |\n"));
+        assert!(!is_incomplete("| ls\n"));
+    }
+
+    #[test]
+    fn test_parser_16() {
+        let cases = [
+            ("| ls", "|", 0..1),
+            ("echo a | | wc", "|", 9..10),
+            ("echo >", "newline", 6..6),
+            ("echo > ; ls", ";", 7..8),
+            ("echo 2>\nls", "newline", 7..8),
+            ("ls; fi", "fi", 4..6),
+            ("echo $(true; | x)", "|", 13..14),
+            ("echo a; ; echo b", ";", 8..9),
+        ];
+        for (input, token, span) in cases {
+            println!("Parsing {input}");
+            let error = run_parser(input).unwrap_err();
+            assert_eq!(
+                error,
+                SyntaxError::new(SyntaxErrorKind::UnexpectedToken(token.to_string()), span)
+            );
+        }
+
+        let error = run_parser("echo 'abc").unwrap_err();
+        assert_eq!(error.kind, SyntaxErrorKind::Unterminated(String::from("'")));
+        assert_eq!(error.span, 5..6);
+        assert_eq!(
+            error.to_string(),
+            "unexpected EOF while looking for matching `''"
+        );
+    }
+
+    #[test]
+    fn test_parser_17() {
+        let error = run_parser("echo a\n\techo b | | wc\n").unwrap_err();
+        assert_eq!(
+            error.render("echo a\n\techo b | | wc\n"),
+            "syntax error near unexpected token `|'\n\techo b | | wc\n\t         ^\n"
+        );
+        let error = run_parser("echo >").unwrap_err();
+        assert_eq!(
+            error.render("echo >"),
+            "syntax error near unexpected token `newline'\necho >\n      ^\n"
+        );
+        let results = run_parser("ls |\n  wc -l").unwrap();
+        assert!(matches!(&results[..], [Command::Pipe { programs }] if programs.len() == 2));
+    }
+
+    #[test]
+    fn test_parser_18() {
+        let results = run_parser("echo a1>b c 12").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                program("echo", &["a1", "c", "12"]),
+                Command::RedirectTo {
+                    path: String::from("b"),
+                    redirect_mode: RedirectMode::StdOut,
+                    write_mode: WriteFileMode::OverWrite
+                }
+            ]
+        );
+
+        let redirect = |path: &str, redirect_mode, write_mode| Command::RedirectTo {
+            path: String::from(path),
+            redirect_mode,
+            write_mode,
+        };
+        let results = run_parser("wc -l <in 3<&0 2<>log <&-").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                program("wc", &["-l"]),
+                redirect("in", RedirectMode::StdIn, WriteFileMode::Read),
+                redirect("&0", RedirectMode::Fd(3), WriteFileMode::Read),
+                redirect("log", RedirectMode::StdErr, WriteFileMode::ReadWrite),
+                redirect("&-", RedirectMode::StdIn, WriteFileMode::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_19() {
+        assert!(is_incomplete("echo a \\\n"));
+        assert!(is_incomplete("echo a\\\n"));
+        assert!(!is_incomplete("echo a \\\\\n"));
+        assert!(!is_incomplete("echo a # b \\\n"));
+        assert!(!is_incomplete("echo a \\\nb\n"));
+        assert!(is_incomplete("echo 'a \\\n"));
+        assert_eq!(
+            run_parser("ec\\\nho a \\\n b").unwrap(),
+            vec![Command::Program {
+                cmd: String::from("echo"),
+                args: vec![String::from("a"), String::from("b")]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parser_20() {
+        let results = run_parser("[[ (1 -eq 1) && !($x == \"(a)\"|$(echo)) ]]").unwrap();
+        let words = ["(", "1", "-eq", "1", ")", "&&", "!($x == \"(a)\"|$(echo))"];
+        assert_eq!(
+            results,
+            vec![Command::Conditional {
+                words: words.iter().map(|word| String::from(*word)).collect()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parser_21() {
+        let results = run_parser("a && b |\nc ||\n\n d >x").unwrap();
+        let redirect = Command::RedirectTo {
+            path: String::from("x"),
+            redirect_mode: RedirectMode::StdOut,
+            write_mode: WriteFileMode::OverWrite,
+        };
+        let pipe = Command::Pipe {
+            programs: vec![Box::new(program("b", &[])), Box::new(program("c", &[]))],
+        };
+        assert_eq!(
+            results,
+            vec![Command::Or {
+                left: vec![Command::And {
+                    left: vec![program("a", &[])],
+                    right: vec![pipe]
+                }],
+                right: vec![program("d", &[]), redirect]
+            }]
+        );
+        assert!(is_incomplete("true &&\n"));
+        assert!(is_incomplete("true ||"));
+        assert!(!is_incomplete("true &&\necho a\n"));
+        assert!(run_parser("&& true").is_err());
+    }
+
+    #[test]
+    fn test_parser_22() {
+        let results = run_parser("cat <<EOF >out\n$x\nEOF\nwc <<\"A\"\n$y\nA").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                program("cat", &[]),
+                Command::RedirectTo {
+                    path: String::from("$x\n"),
+                    redirect_mode: RedirectMode::StdIn,
+                    write_mode: WriteFileMode::HereDoc { expand: true }
+                },
+                Command::RedirectTo {
+                    path: String::from("out"),
+                    redirect_mode: RedirectMode::StdOut,
+                    write_mode: WriteFileMode::OverWrite
+                },
+                program("wc", &[]),
+                Command::RedirectTo {
+                    path: String::from("$y\n"),
+                    redirect_mode: RedirectMode::StdIn,
+                    write_mode: WriteFileMode::HereDoc { expand: false }
+                },
+            ]
+        );
+        assert!(is_incomplete("cat <<EOF\nabc\n"));
+        assert!(is_incomplete("cat <<EOF\n"));
+        assert!(!is_incomplete("cat <<EOF\nabc\nEOF\n"));
+    }
+
+    #[test]
+    fn test_parser_23() {
+        let results = run_parser("sleep 1 && echo a >x & wait\n{ b & }").unwrap();
+        let redirect = Command::RedirectTo {
+            path: String::from("x"),
+            redirect_mode: RedirectMode::StdOut,
+            write_mode: WriteFileMode::OverWrite,
+        };
+        assert_eq!(
+            results,
+            vec![
+                Command::Background {
+                    body: vec![Command::And {
+                        left: vec![program("sleep", &["1"])],
+                        right: vec![program("echo", &["a"]), redirect]
+                    }],
+                    command: String::from("sleep 1 && echo a >x")
+                },
+                program("wait", &[]),
+                Command::Group {
+                    body: vec![Command::Background {
+                        body: vec![program("b", &[])],
+                        command: String::from("b")
+                    }]
+                }
+            ]
+        );
+        assert!(run_parser("& echo").is_err());
+    }
+
+    #[test]
+    fn test_parser_24() {
+        let results = run_parser("f() { echo in; } 2>&1 >/dev/null; f").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                Command::FunctionDef {
+                    name: String::from("f"),
+                    body: Box::new(Command::Group {
+                        body: vec![program("echo", &["in"])]
+                    }),
+                    redirects: vec![
+                        Command::RedirectTo {
+                            path: String::from("&1"),
+                            redirect_mode: RedirectMode::StdErr,
+                            write_mode: WriteFileMode::OverWrite,
+                        },
+                        Command::RedirectTo {
+                            path: String::from("/dev/null"),
+                            redirect_mode: RedirectMode::StdOut,
+                            write_mode: WriteFileMode::OverWrite,
+                        }
+                    ]
+                },
+                program("f", &[])
+            ]
+        );
+    }
 }
@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod test_printf {
+    use codecrafters_shell::printf::{expand_escapes, format};
+
+    fn printf(format_str: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let formatted = format(format_str, &args);
+        String::from_utf8(formatted.output).unwrap()
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(printf("%s=%d\\n", &["x", "42"]), "x=42\n");
+        assert_eq!(
+            printf("%x %X %o %#x %#o", &["255", "255", "8", "255", "8"]),
+            "ff FF 10 0xff 010"
+        );
+        assert_eq!(printf("%d %d %d", &["0x10", "010", "'A"]), "16 8 65");
+        assert_eq!(
+            printf("%.2f %e %g %g", &["3.14159", "12345.678", "0.0001", "1e20"]),
+            "3.14 1.234568e+04 0.0001 1e+20"
+        );
+        assert_eq!(printf("%c%%", &["hello"]), "h%");
+        assert_eq!(printf("%q %q %q", &["a b", "", "it's"]), "a\\ b '' it\\'s");
+    }
+
+    #[test]
+    fn test_width_and_precision() {
+        assert_eq!(
+            printf("[%5d|%-5s|%05d|%+d]", &["42", "ab", "-42", "7"]),
+            "[   42|ab   |-0042|+7]"
+        );
+        assert_eq!(
+            printf("[%.3s|%5.2s|%.3d]", &["abcdef", "xyz", "7"]),
+            "[abc|   xy|007]"
+        );
+        assert_eq!(
+            printf("[%*d|%.*f]", &["4", "1", "2", "3.14159"]),
+            "[   1|3.14]"
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_width() {
+        let args = ["99999999999".to_string(), "x".to_string()];
+        for format_str in ["a%*s|", "a%.*s|", "a%99999999999s|"] {
+            let formatted = format(format_str, &args);
+            assert_eq!(formatted.output, b"a");
+            assert_eq!(formatted.errors.len(), 1);
+            assert!(formatted.errors[0].ends_with(": Numerical result out of range"));
+        }
+    }
+
+    #[test]
+    fn test_format_reuse() {
+        assert_eq!(printf("%s,", &["a", "b", "c"]), "a,b,c,");
+        assert_eq!(printf("%s=%d ", &["a", "1", "b"]), "a=1 b=0 ");
+        assert_eq!(printf("plain\\n", &["ignored"]), "plain\n");
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(printf("%b|%s", &["a\\tb", "\\t"]), "a\tb|\\t");
+        assert_eq!(printf("%b%s", &["stop\\chere", "never"]), "stop");
+        assert_eq!(printf("\\101\\x42\\u00e9", &[]), "ABé");
+        assert_eq!(
+            expand_escapes("a\\0101\\101\\c", true),
+            (b"aA\\101".to_vec(), true)
+        );
+        assert_eq!(expand_escapes("\\xffz", false), (vec![0xff, b'z'], false));
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        let formatted = format("%d|%d", &["12abc".to_string(), "3".to_string()]);
+        assert_eq!(formatted.output, b"0|3");
+        assert_eq!(formatted.errors, vec!["12abc: invalid number"]);
+    }
+}
@@ -0,0 +1,228 @@
+#[cfg(test)]
+mod test_shell {
+    use std::io::Write;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use codecrafters_shell::builtins::{Builtin, BuiltinIo};
+    use codecrafters_shell::shell::Shell;
+    use codecrafters_shell::state::ShellState;
+
+    /// The tests change the process' working directory and standard file
+    /// descriptors, so they take turns rather than run in parallel.
+    static PROCESS: Mutex<()> = Mutex::new(());
+
+    fn lock_process() -> MutexGuard<'static, ()> {
+        PROCESS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    struct Greet;
+
+    impl Builtin for Greet {
+        fn name(&self) -> &str {
+            "greet"
+        }
+
+        fn help(&self) -> &str {
+            "greet [name]"
+        }
+
+        fn run(
+            &self,
+            args: &[String],
+            _env: &[(String, String)],
+            io: &mut BuiltinIo,
+            _state: &mut ShellState,
+        ) -> i32 {
+            let name = args.first().map_or("world", String::as_str);
+            let _ = writeln!(io.stdout, "hello {name}");
+            0
+        }
+    }
+
+    #[test]
+    fn test_run_line_and_history() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        assert_eq!(shell.run_line("x=1"), 0);
+        assert_eq!(shell.run_line("  "), 0);
+        assert_eq!(shell.run_line("f() { return 7; }; f"), 7);
+        assert_eq!(shell.last_status(), 7);
+        assert_eq!(shell.history(), ["x=1", "f() { return 7; }; f"]);
+        assert_eq!(shell.var("x"), Some("1"));
+        assert!(shell.function("f").is_some());
+
+        assert_eq!(shell.exit_status(), None);
+        shell.run_line("exit 2");
+        assert_eq!(shell.exit_status(), Some(2));
+    }
+
+    #[test]
+    fn test_variables() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        shell.set_var("NAME", "a b");
+        shell.run_script("COPY=\"$NAME!\"");
+        assert_eq!(shell.var("COPY"), Some("a b!"));
+
+        shell.export_var("SHELL_TEST_EXPORTED", "yes");
+        let output = shell.capture("printenv SHELL_TEST_EXPORTED");
+        assert_eq!(output.stdout_lossy(), "yes\n");
+
+        shell.unset_var("NAME");
+        assert_eq!(shell.var("NAME"), None);
+    }
+
+    #[test]
+    fn test_capture() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        let output = shell.capture("echo out; echo err >&2; x=changed; exit 3");
+        assert_eq!(output.status, 3);
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr_lossy(), "err\n");
+        assert_eq!(shell.var("x"), None);
+        assert_eq!(shell.exit_status(), None);
+    }
+
+    #[test]
+    fn test_registered_builtins() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        assert!(shell.builtins().contains("cd"));
+        shell.builtins_mut().register(Greet);
+        assert_eq!(
+            shell.capture("greet; greet you").stdout_lossy(),
+            "hello world\nhello you\n"
+        );
+        assert_eq!(
+            shell.capture("type greet").stdout_lossy(),
+            "greet is a shell builtin\n"
+        );
+    }
+
+    #[test]
+    fn test_cd() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        let start = shell.cwd().to_path_buf();
+        shell.run_line("cd /");
+        assert_eq!(shell.cwd().to_str(), Some("/"));
+        assert_eq!(shell.var("OLDPWD"), start.to_str());
+        assert_eq!(shell.capture("pwd").stdout_lossy(), "/\n");
+
+        let output = shell.capture("cd /nonexistent");
+        assert_eq!(output.status, 1);
+        assert!(output.stderr_lossy().starts_with("cd: /nonexistent: "));
+
+        shell.set_cwd(&start).unwrap();
+        assert_eq!(shell.cwd(), start);
+        assert_eq!(shell.capture("cd -").stdout_lossy(), "/\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        let output = shell.capture("no_such_command_here; echo after");
+        assert_eq!(
+            output.stderr_lossy(),
+            "no_such_command_here: command not found\n"
+        );
+        assert_eq!(output.stdout_lossy(), "after\n");
+
+        let output = shell.capture("echo hi > /nonexistent/file");
+        assert_eq!(output.status, 1);
+        assert_eq!(
+            output.stderr_lossy(),
+            "/nonexistent/file: No such file or directory\n"
+        );
+
+        let output = shell.capture("if true; then echo never");
+        assert_eq!(output.status, 2);
+        assert_eq!(
+            output.stderr_lossy(),
+            "syntax error: unexpected end of file\n"
+        );
+        assert_eq!(shell.run_script("eval 'for x in a; do'"), 2);
+
+        let output = shell.capture("echo $((1/0)); echo after $?");
+        assert_eq!(output.stdout_lossy(), "after 1\n");
+
+        let output = shell.capture("for 1x in a; do :; done; ((1 / 0)) 2>&1");
+        assert_eq!(output.status, 1);
+        assert_eq!(output.stderr_lossy(), "`1x': not a valid identifier\n");
+        assert!(
+            output
+                .stdout_lossy()
+                .starts_with("((: 1 / 0: division by 0")
+        );
+
+        let output = shell.capture("printf '%*s' 99999999999 x; echo $?");
+        assert_eq!(output.stdout_lossy(), "1\n");
+        assert_eq!(
+            output.stderr_lossy(),
+            "printf: 99999999999: Numerical result out of range\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_line_commands() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        let line = "for x in a \\\nb\ndo echo \"$x\n\"\ndone\n";
+        assert_eq!(shell.run_line(line), 0);
+        assert_eq!(shell.history(), [line.trim()]);
+        let output = shell.capture("echo one \\\ntwo |\ncat");
+        assert_eq!(output.stdout_lossy(), "one two\n");
+    }
+
+    #[test]
+    fn test_function_redirects() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        let output = shell.capture("f() { echo in; } > /dev/null; f; echo out");
+        assert_eq!(output.stdout_lossy(), "out\n");
+        let output = shell.capture("g() { echo \"$1\" >&2; return 4; } 2>&1; g err; echo $?");
+        assert_eq!(output.stdout_lossy(), "err\n4\n");
+        assert_eq!(output.stderr_lossy(), "");
+    }
+
+    #[test]
+    fn test_process_substitutions() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        let output = shell.capture("f() { :; cat \"$1\"; }; f <(echo hi)");
+        assert_eq!(output.stdout_lossy(), "hi\n");
+        // The loop must not wait for `seq` while it is blocked writing.
+        let output = shell.capture("while read l; do n=$((n+1)); done < <(seq 20000); echo $n");
+        assert_eq!(output.stdout_lossy(), "20000\n");
+    }
+
+    #[test]
+    fn test_jobs() {
+        let _process = lock_process();
+        let mut shell = Shell::new();
+        assert_eq!(shell.run_script("sleep 5 & (exit 3) &"), 0);
+        let jobs: Vec<_> = shell.jobs().iter().map(|job| job.command.clone()).collect();
+        assert_eq!(jobs, ["sleep 5", "(exit 3)"]);
+        let pid = shell.jobs()[1].pid;
+
+        let output = shell.capture("jobs");
+        assert_eq!(
+            output.stdout_lossy(),
+            "[1]-  Running                 sleep 5 &\n[2]+  Running                 (exit 3) &\n"
+        );
+        assert_eq!(shell.capture("echo $!").stdout_lossy(), format!("{pid}\n"));
+        assert_eq!(shell.run_script("wait %2"), 3);
+        assert_eq!(shell.run_script("kill $(jobs -p)"), 0);
+        shell.wait_jobs();
+        assert!(shell.jobs().is_empty());
+
+        let output = shell.capture("wait 1; wait %3");
+        assert_eq!(
+            output.stderr_lossy(),
+            "wait: pid 1 is not a child of this shell\nwait: %3: no such job\n"
+        );
+        assert_eq!(output.status, 127);
+    }
+}
@@ -1,6 +1,10 @@
 #[cfg(test)]
 mod test_utils {
-    use codecrafters_shell::utils::strip_bytes;
+    use std::io;
+
+    use codecrafters_shell::enums::WriteFileMode;
+    use codecrafters_shell::error::ShellError;
+    use codecrafters_shell::utils::{dump_history, fill_history, strip_bytes};
 
     #[test]
     fn test_clean_bytes_1() {
@@ -16,4 +20,38 @@ mod test_utils {
         let results = strip_bytes(s.to_vec());
         assert_eq!(results, *expected);
     }
+
+    #[test]
+    fn test_history_files() {
+        let path = std::env::temp_dir().join(format!("history-{}", std::process::id()));
+        let mut history = vec![String::from("echo a"), String::from("ls")];
+        dump_history(&path, &mut history, WriteFileMode::OverWrite).unwrap();
+        assert_eq!(history.len(), 2);
+        let mut appended = vec![String::from("pwd")];
+        dump_history(&path, &mut appended, WriteFileMode::Append).unwrap();
+        assert!(appended.is_empty());
+
+        let mut read = vec![];
+        fill_history(&path, &mut read).unwrap();
+        assert_eq!(read, ["echo a", "ls", "pwd"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_history_file_errors() {
+        let mut history = vec![String::from("echo a")];
+        let error = fill_history("/nonexistent/history", &mut history).unwrap_err();
+        assert!(
+            matches!(&error, ShellError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+        );
+        assert_eq!(
+            error.to_string(),
+            "/nonexistent/history: No such file or directory"
+        );
+        assert_eq!(error.status(), 1);
+
+        let error = dump_history("/nonexistent/history", &mut history, WriteFileMode::Append);
+        assert!(error.is_err());
+        assert_eq!(history, ["echo a"]);
+    }
 }